@@ -0,0 +1,327 @@
+use std::io::{self, BufRead, Read, Write};
+use std::fs::File;
+use std::path::Path;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::utils::{ConsoleInteractor, json_u64};
+
+// clique marks in-turn blocks with difficulty 2 and out-of-turn blocks with 1
+pub const DIFF_IN_TURN: u64 = 2;
+
+#[derive(Debug, Clone)]
+pub struct BlockInfo {
+    pub number:     u64,
+    pub hash:       String,
+    pub timestamp:  u64,
+    pub tx_count:   usize,
+    pub tx_hashes:  Vec<String>,
+    pub gas_used:   u64,
+    pub gas_limit:  u64,
+    pub uncles:     usize,
+    pub difficulty: u64,
+}
+
+impl BlockInfo {
+    pub fn from_json(block: &serde_json::Value) -> BlockInfo {
+        let txs = block["transactions"].as_array().unwrap();
+        BlockInfo {
+            number:     json_u64(&block["number"]),
+            hash:       String::from(block["hash"].as_str().unwrap()),
+            timestamp:  json_u64(&block["timestamp"]),
+            tx_count:   txs.len(),
+            tx_hashes:  txs.iter().map(|h| String::from(h.as_str().unwrap())).collect(),
+            gas_used:   json_u64(&block["gasUsed"]),
+            gas_limit:  json_u64(&block["gasLimit"]),
+            uncles:     block["uncles"].as_array().map_or(0, |u| u.len()),
+            difficulty: json_u64(&block["difficulty"]),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Distribution {
+    pub min:    f64,
+    pub max:    f64,
+    pub mean:   f64,
+    pub p50:    f64,
+    pub p90:    f64,
+    pub p99:    f64,
+}
+
+impl Distribution {
+    pub fn from_samples(samples: &[f64]) -> Distribution {
+        if samples.is_empty() {
+            return Distribution::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Distribution {
+            min:    sorted[0],
+            max:    sorted[sorted.len()-1],
+            mean:   sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50:    percentile(&sorted, 50.0),
+            p90:    percentile(&sorted, 90.0),
+            p99:    percentile(&sorted, 99.0),
+        }
+    }
+}
+
+// nearest-rank percentile over sorted samples
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChainSummary {
+    pub first_block:        u64,
+    pub last_block:         u64,
+    pub blocks:             usize,
+    pub transactions:       usize,
+    pub gas_used:           u64,
+    pub duration_secs:      u64,
+    pub tps:                f64,
+    pub window_tps:         Distribution,
+    pub txs_per_block:      Distribution,
+    pub block_interval:     Distribution,
+    pub empty_ratio:        f64,
+    pub in_turn_ratio:      f64,
+    pub uncles:             usize,
+    pub reorgs:             usize,
+    pub max_reorg_depth:    u64,
+}
+
+// walks the blocks produced during a test on a reference node
+pub struct ChainAnalyzer {
+    window:     u64,
+    base:       Option<BlockInfo>,
+    blocks:     Vec<BlockInfo>,
+    observed:   Vec<(u64, String)>,
+}
+
+impl ChainAnalyzer {
+    pub fn new(window: u64) -> ChainAnalyzer {
+        ChainAnalyzer {
+            window:     window.max(1),
+            base:       None,
+            blocks:     Vec::new(),
+            observed:   Vec::new(),
+        }
+    }
+
+    // records a head seen while the test was running, used to detect reorgs afterwards
+    pub fn observe_head<T, U>(&mut self, itr: &mut ConsoleInteractor<T, U>)
+        where T: Read + BufRead, U: Write
+    {
+        let head = itr.send_for_json("eth.getBlock(\"latest\")");
+        let head = BlockInfo::from_json(&head);
+        self.observed.push((head.number, head.hash));
+    }
+
+    // fetches the blocks in (from, to], with block `from` kept as the time base
+    pub fn fetch<T, U>(&mut self, itr: &mut ConsoleInteractor<T, U>, from: u64, to: u64)
+        where T: Read + BufRead, U: Write
+    {
+        let base = itr.send_for_json(&format!("eth.getBlock({})", from));
+        self.base = Some(BlockInfo::from_json(&base));
+        for n in from+1..=to {
+            let block = itr.send_for_json(&format!("eth.getBlock({})", n));
+            self.blocks.push(BlockInfo::from_json(&block));
+        }
+    }
+
+    pub fn blocks(&self) -> &[BlockInfo] {
+        &self.blocks
+    }
+
+    pub fn summarize(&self) -> ChainSummary {
+        let mut summary = ChainSummary::default();
+        let base = match self.base {
+            Some(ref base) => base,
+            None => return summary,
+        };
+        summary.first_block = base.number + 1;
+        summary.last_block = self.blocks.last().map_or(base.number, |b| b.number);
+        summary.blocks = self.blocks.len();
+        if self.blocks.is_empty() {
+            return summary;
+        }
+
+        summary.transactions = self.blocks.iter().map(|b| b.tx_count).sum();
+        summary.gas_used = self.blocks.iter().map(|b| b.gas_used).sum();
+        summary.uncles = self.blocks.iter().map(|b| b.uncles).sum();
+        summary.duration_secs = self.blocks[self.blocks.len()-1].timestamp - base.timestamp;
+        if summary.duration_secs > 0 {
+            summary.tps = summary.transactions as f64 / summary.duration_secs as f64;
+        }
+
+        let txs: Vec<f64> = self.blocks.iter().map(|b| b.tx_count as f64).collect();
+        summary.txs_per_block = Distribution::from_samples(&txs);
+
+        let mut prev = base.timestamp;
+        let mut intervals = Vec::with_capacity(self.blocks.len());
+        for b in &self.blocks {
+            intervals.push((b.timestamp - prev) as f64);
+            prev = b.timestamp;
+        }
+        summary.block_interval = Distribution::from_samples(&intervals);
+
+        let empty = self.blocks.iter().filter(|b| b.tx_count == 0).count();
+        summary.empty_ratio = empty as f64 / self.blocks.len() as f64;
+        let in_turn = self.blocks.iter().filter(|b| b.difficulty == DIFF_IN_TURN).count();
+        summary.in_turn_ratio = in_turn as f64 / self.blocks.len() as f64;
+
+        summary.window_tps = Distribution::from_samples(&self.window_tps());
+        let (reorgs, depth) = self.reorgs();
+        summary.reorgs = reorgs;
+        summary.max_reorg_depth = depth;
+
+        summary
+    }
+
+    // throughput over the `window` seconds ending at each block
+    fn window_tps(&self) -> Vec<f64> {
+        let start = self.base.as_ref().unwrap().timestamp;
+        let mut res = Vec::new();
+        let mut lo = 0;
+        let mut in_window = 0;
+        for b in &self.blocks {
+            in_window += b.tx_count;
+            while self.blocks[lo].timestamp + self.window <= b.timestamp {
+                in_window -= self.blocks[lo].tx_count;
+                lo += 1;
+            }
+            // skip windows that are not yet full at the beginning of the test
+            if b.timestamp >= start + self.window {
+                res.push(in_window as f64 / self.window as f64);
+            }
+        }
+        res
+    }
+
+    // counts observed heads that did not end up on the canonical chain; the depth of
+    // a reorg is bounded by the last head observed before it that is still canonical
+    fn reorgs(&self) -> (usize, u64) {
+        let mut reorgs = 0;
+        let mut max_depth = 0;
+        let mut last_canonical = self.base.as_ref().map_or(0, |b| b.number);
+        let mut last: Option<&(u64, String)> = None;
+        for obs in &self.observed {
+            if last == Some(obs) {
+                continue;
+            }
+            last = Some(obs);
+            match self.blocks.iter().find(|b| b.number == obs.0) {
+                Some(b) if b.hash != obs.1 => {
+                    reorgs += 1;
+                    max_depth = max_depth.max(obs.0 - last_canonical.min(obs.0 - 1));
+                },
+                Some(_) => last_canonical = obs.0,
+                None => (),
+            }
+        }
+        (reorgs, max_depth)
+    }
+
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "number,hash,timestamp,interval,tx_count,gas_used,gas_limit,uncles,in_turn")?;
+        let mut prev = self.base.as_ref().map_or(0, |b| b.timestamp);
+        for b in &self.blocks {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{},{}",
+                b.number, b.hash, b.timestamp, b.timestamp - prev, b.tx_count,
+                b.gas_used, b.gas_limit, b.uncles, b.difficulty == DIFF_IN_TURN,
+            )?;
+            prev = b.timestamp;
+        }
+        Ok(())
+    }
+}
+
+impl ChainSummary {
+    pub fn print(&self) {
+        println!("Chain analysis for blocks {}..={}", self.first_block, self.last_block);
+        println!("  blocks:              {}", self.blocks);
+        println!("  transactions:        {}", self.transactions);
+        println!("  gas used:            {}", self.gas_used);
+        println!("  duration:            {}s", self.duration_secs);
+        println!("  TPS:                 {:.2}", self.tps);
+        println!("  TPS (sliding):       {}", self.window_tps);
+        println!("  txs per block:       {}", self.txs_per_block);
+        println!("  block interval (s):  {}", self.block_interval);
+        println!("  empty blocks:        {:.1}%", self.empty_ratio * 100.0);
+        println!("  in-turn blocks:      {:.1}%", self.in_turn_ratio * 100.0);
+        println!("  uncles:              {}", self.uncles);
+        println!("  reorgs:              {} (max depth {})", self.reorgs, self.max_reorg_depth);
+    }
+}
+
+impl std::fmt::Display for Distribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "min {:.2} / mean {:.2} / p50 {:.2} / p90 {:.2} / p99 {:.2} / max {:.2}",
+            self.min, self.mean, self.p50, self.p90, self.p99, self.max,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, timestamp: u64, tx_count: usize) -> BlockInfo {
+        BlockInfo {
+            number,
+            hash:       format!("0x{:x}", number),
+            timestamp,
+            tx_count,
+            tx_hashes:  Vec::new(),
+            gas_used:   21000 * tx_count as u64,
+            gas_limit:  8000000,
+            uncles:     0,
+            difficulty: DIFF_IN_TURN,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut ca = ChainAnalyzer::new(10);
+        ca.base = Some(block(10, 100, 3));
+        ca.blocks = (11..=20).map(|n| block(n, 100 + (n - 10) * 5, (n % 2) as usize * 10)).collect();
+        let s = ca.summarize();
+        assert_eq!(s.blocks, 10);
+        assert_eq!(s.transactions, 50);
+        assert_eq!(s.duration_secs, 50);
+        assert!((s.tps - 1.0).abs() < 1e-9);
+        assert!((s.empty_ratio - 0.5).abs() < 1e-9);
+        assert_eq!(s.block_interval.mean, 5.0);
+        // every full 10s window holds exactly one non-empty block
+        assert_eq!(s.window_tps.min, 1.0);
+        assert_eq!(s.window_tps.max, 1.0);
+    }
+
+    #[test]
+    fn test_reorgs() {
+        let mut ca = ChainAnalyzer::new(10);
+        ca.base = Some(block(0, 0, 0));
+        ca.blocks = (1..=5).map(|n| block(n, n * 5, 1)).collect();
+        ca.observed = vec![(2, String::from("0x2")), (3, String::from("0xdead")), (4, String::from("0x4"))];
+        assert_eq!(ca.reorgs(), (1, 1));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<f64> = (1..=100).map(|x| x as f64).collect();
+        assert_eq!(percentile(&samples, 50.0), 50.0);
+        assert_eq!(percentile(&samples, 90.0), 90.0);
+        assert_eq!(percentile(&samples, 100.0), 100.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+}
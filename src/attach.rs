@@ -0,0 +1,217 @@
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use toml::Value;
+
+use crate::propagation;
+use crate::signers::attach_exec;
+use crate::state::RunState;
+use crate::verify::ChainView;
+use crate::utils::{self, Console, ConsoleInteractor, ChildReader, ChildWriter};
+
+// `attach` and `exec` subcommands, reach the nodes of a running network over the
+// ipc endpoints recorded in its run state
+pub struct NodeConsole {
+    geth_dir:   PathBuf,
+    state:      RunState,
+}
+
+// sends one JSON-RPC request as is and returns the response
+pub fn rpc_call(endpoint: &str, request: &str) -> io::Result<serde_json::Value> {
+    let mut conn = propagation::connect(endpoint)?;
+    conn.write_all(request.as_bytes())?;
+    conn.write_all(b"\n")?;
+    let mut resps = serde_json::Deserializer::from_reader(&conn).into_iter::<serde_json::Value>();
+    match resps.next() {
+        Some(resp) => Ok(resp?),
+        None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Node closed the connection")),
+    }
+}
+
+// calls one JSON-RPC method and returns its result, an error response counts as failed
+pub fn rpc_request(endpoint: &str, method: &str, params: serde_json::Value) -> io::Result<serde_json::Value> {
+    let request = serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    let mut resp = rpc_call(endpoint, &request.to_string())?;
+    if let Some(err) = resp.get("error") {
+        return Err(io::Error::other(format!("{}: {}", method, err)));
+    }
+    Ok(resp["result"].take())
+}
+
+impl NodeConsole {
+    pub fn new_with_cfg_file(path: &Path) -> NodeConsole {
+        NodeConsole::new_with_cfg(&utils::read_toml(path))
+    }
+
+    pub fn new_with_cfg(parsed: &Value) -> NodeConsole {
+        let nodes_dir = PathBuf::from(parsed["node"]["dir"].as_str().unwrap());
+        let state = RunState::load(&nodes_dir)
+            .unwrap_or_else(|e| panic!("Read run state in {} failed, is the network running? {}", nodes_dir.display(), e));
+        if !utils::pid_alive(state.runner_pid) {
+            println!("Runner {} of the run state is gone, the state may be stale", state.runner_pid);
+        }
+        NodeConsole {
+            geth_dir:   PathBuf::from(parsed["bin"]["geth_dir"].as_str().unwrap()),
+            state,
+        }
+    }
+
+    // runs `expr` on each node and prints what it evaluates to, returns whether it
+    // succeeded everywhere
+    pub fn exec(&self, nodes: Option<usize>, expr: &str) -> bool {
+        let ids: Vec<usize> = match nodes {
+            Some(id) => vec![self.state.node(id).id],
+            None => (0..self.state.nodes.len()).collect(),
+        };
+        let mut ok = true;
+        for id in ids {
+            let node = self.state.node(id);
+            if !node.running {
+                println!("node {}: not running", id);
+                ok = false;
+                continue;
+            }
+            match attach_exec(&self.geth_dir, &node.ipc, expr) {
+                Ok(resp) => println!("node {}: {}", id, resp),
+                Err(e) => {
+                    println!("node {}: error: {}", id, e.to_string().trim());
+                    ok = false;
+                },
+            }
+        }
+        ok
+    }
+
+    // a console on the node until `exit` or end of input, lines starting with `{`
+    // go to the node as raw JSON-RPC requests and the rest is evaluated as js
+    pub fn attach(&self, id: usize) {
+        let node = self.state.node(id);
+        if !node.running {
+            panic!("Node {} is not running", id);
+        }
+        let mut geth = Command::new(&self.geth_dir)
+            .arg("attach")
+            .arg(&node.ipc)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        utils::set_console_echo(false);
+        let console = Console::
+            <ChildReader, ChildWriter>::
+            from_child(&mut geth, &format!("node {}", id));
+        let mut itr = ConsoleInteractor::new(console);
+        // skips the welcome message up to the first prompt
+        let mut buf = Vec::new();
+        itr.recv(&mut buf).expect("Attach to node failed");
+        println!("Attached to node {} at {}, `exit` to leave", id, node.ipc);
+
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        for n in 0.. {
+            print!("node {}> ", id);
+            io::stdout().flush().unwrap();
+            let line = match lines.next() {
+                Some(line) => line.unwrap(),
+                None => break,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "exit" {
+                break;
+            }
+            if line.starts_with('{') {
+                match rpc_call(&node.ipc, line) {
+                    Ok(resp) => println!("{}", resp),
+                    Err(e) => println!("error: {}", e),
+                }
+            } else {
+                match itr.send_with_marker(line.as_bytes(), &format!("ethrunner-{}", n)) {
+                    Ok(resp) => println!("{}", resp),
+                    Err(e) => {
+                        println!("error: {}", e);
+                        break;
+                    },
+                }
+            }
+        }
+        drop(itr);
+        geth.wait().unwrap();
+    }
+
+    fn eval(&self, id: usize, expr: &str) -> Option<String> {
+        let node = self.state.node(id);
+        if !node.running {
+            return None;
+        }
+        attach_exec(&self.geth_dir, &node.ipc, expr).ok()
+    }
+}
+
+impl ChainView for NodeConsole {
+    fn node_count(&self) -> usize {
+        self.state.nodes.len()
+    }
+
+    // an unreachable node counts as stopped
+    fn try_head(&mut self, id: usize) -> Option<(u64, String)> {
+        let resp = self.eval(id, r#"(function(b) { return b.number + " " + b.hash })(eth.getBlock("latest"))"#)?;
+        let (number, hash) = resp.split_once(' ')?;
+        Some((number.parse().ok()?, String::from(hash)))
+    }
+
+    // empty when the node has no block at that height
+    fn block_hash(&mut self, id: usize, number: u64) -> String {
+        self.eval(id, &format!("(function(b) {{ return b ? b.hash : \"\" }})(eth.getBlock({}))", number)).unwrap_or_default()
+    }
+
+    fn running_nodes(&mut self) -> Vec<usize> {
+        self.state.nodes.iter().filter(|n| n.running).map(|n| n.id).collect()
+    }
+}
+
+// a fake node for tests, serves `requests` JSON-RPC requests at an ipc endpoint
+// in the temp dir, one connection per request as rpc_call makes them, and
+// answers each with the result `respond` gives for it
+#[cfg(all(test, unix))]
+pub(crate) fn fake_ipc<F>(name: &str, requests: usize, mut respond: F) -> (PathBuf, std::thread::JoinHandle<()>)
+    where F: FnMut(&serde_json::Value) -> serde_json::Value + Send + 'static
+{
+    use std::io::BufReader;
+    use std::os::unix::net::UnixListener;
+
+    let endpoint = std::env::temp_dir().join(format!("ethrunner-{}-{}.ipc", name, std::process::id()));
+    let _ = std::fs::remove_file(&endpoint);
+    let listener = UnixListener::bind(&endpoint).unwrap();
+    let server = std::thread::spawn(move || {
+        for _ in 0..requests {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut req = String::new();
+            BufReader::new(&conn).read_line(&mut req).unwrap();
+            let result = respond(&serde_json::from_str(&req).unwrap());
+            conn.write_all(serde_json::json!({"jsonrpc": "2.0", "id": 1, "result": result}).to_string().as_bytes()).unwrap();
+        }
+    });
+    (endpoint, server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rpc_call() {
+        let (endpoint, server) = fake_ipc("attach", 1, |req| {
+            assert_eq!(req["method"], "eth_blockNumber");
+            serde_json::json!("0x2a")
+        });
+        let resp = rpc_call(endpoint.to_str().unwrap(), r#"{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber","params":[]}"#).unwrap();
+        server.join().unwrap();
+        assert_eq!(resp["result"], "0x2a");
+        std::fs::remove_file(&endpoint).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_derive::{Serialize, Deserialize};
+use toml::Value;
+
+use crate::run::NodeRunner;
+
+// distribution of session or downtime lengths in seconds, e.g.
+//
+//     session = { dist = "exponential", mean = 60 }
+//     downtime = { dist = "uniform", min = 5, max = 20 }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dist {
+    Fixed(f64),
+    Uniform(f64, f64),
+    Exponential(f64),
+    // heavy-tailed session lengths as observed in peer-to-peer measurements
+    Pareto { shape: f64, scale: f64 },
+}
+
+impl Dist {
+    pub fn from_toml(v: &Value) -> Dist {
+        let f = |key: &str| {
+            let v = &v[key];
+            v.as_float().or_else(|| v.as_integer().map(|i| i as f64)).unwrap()
+        };
+        match v["dist"].as_str().unwrap() {
+            "fixed" => Dist::Fixed(f("value")),
+            "uniform" => Dist::Uniform(f("min"), f("max")),
+            "exponential" => Dist::Exponential(f("mean")),
+            "pareto" => Dist::Pareto { shape: f("shape"), scale: f("scale") },
+            d => panic!("Unknown distribution {}", d),
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        let secs = match *self {
+            Dist::Fixed(v) => v,
+            Dist::Uniform(lo, hi) => rng.gen_range(lo..=hi),
+            Dist::Exponential(mean) => -mean * (1.0 - rng.gen::<f64>()).ln(),
+            Dist::Pareto { shape, scale } => scale / (1.0 - rng.gen::<f64>()).powf(1.0 / shape),
+        };
+        Duration::from_secs_f64(secs.max(0.0))
+    }
+}
+
+pub struct ChurnConfig {
+    session:            Dist,
+    downtime:           Dist,
+    include_sealers:    bool,
+}
+
+impl ChurnConfig {
+    pub fn new_with_cfg(parsed: &Value) -> Option<ChurnConfig> {
+        let churn = parsed.get("churn")?;
+        if !churn.get("enabled").is_some_and(|v| v.as_bool().unwrap()) {
+            return None;
+        }
+        Some(ChurnConfig {
+            session:            Dist::from_toml(&churn["session"]),
+            downtime:           Dist::from_toml(&churn["downtime"]),
+            include_sealers:    churn.get("include_sealers").is_some_and(|v| v.as_bool().unwrap()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Availability {
+    pub node:           usize,
+    pub restarts:       usize,
+    pub downtime_ms:    u64,
+    // fraction of the test during which the node was running
+    pub availability:   f64,
+}
+
+pub struct Churn {
+    session:        Dist,
+    downtime:       Dist,
+    rng:            StdRng,
+    start:          Instant,
+    // nodes taking part in the churn and when each of them toggles next
+    next:           Vec<(usize, Instant)>,
+    down_since:     Vec<Option<Instant>>,
+    downtime_total: Vec<Duration>,
+    restarts:       Vec<usize>,
+}
+
+impl Churn {
+    pub fn new(cfg: &ChurnConfig, nodes: &[usize], node_count: usize, seed: u64) -> Churn {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let now = Instant::now();
+        let next = nodes.iter().map(|&id| (id, now + cfg.session.sample(&mut rng))).collect();
+        Churn {
+            session:        cfg.session,
+            downtime:       cfg.downtime,
+            rng,
+            start:          now,
+            next,
+            down_since:     vec![None; node_count],
+            downtime_total: vec![Duration::ZERO; node_count],
+            restarts:       vec![0; node_count],
+        }
+    }
+
+    pub fn availability(&self) -> Vec<Availability> {
+        let elapsed = self.start.elapsed().as_secs_f64().max(f64::EPSILON);
+        (0..self.restarts.len()).map(|id| {
+            let down = self.downtime_total[id] + self.down_since[id].map_or(Duration::ZERO, |t| t.elapsed());
+            Availability {
+                node:           id,
+                restarts:       self.restarts[id],
+                downtime_ms:    down.as_millis() as u64,
+                availability:   1.0 - down.as_secs_f64() / elapsed,
+            }
+        }).collect()
+    }
+}
+
+impl NodeRunner {
+    // starts churning the eligible nodes, the reference node is always kept up
+    pub(crate) fn begin_churn(&mut self, cfg: &ChurnConfig, reference: usize) -> Churn {
+        let nodes: Vec<usize> = (0..self.node_count())
+            .filter(|&id| id != reference && (cfg.include_sealers || !self.is_signer(id)))
+            .collect();
+        println!("Churn: cycling nodes {:?}", nodes);
+        Churn::new(cfg, &nodes, self.node_count(), self.seed())
+    }
+
+    // stops or restarts the nodes whose session or downtime is over, refreshing the
+    // nonce of restarted nodes since their pending transactions may be gone
+    pub(crate) fn churn_tick(&mut self, churn: &mut Churn, nonces: &mut [usize]) {
+        let now = Instant::now();
+        for i in 0..churn.next.len() {
+            let (id, at) = churn.next[i];
+            if at > now {
+                continue;
+            }
+            if self.is_running(id) {
+                println!("Churn: stopping node {}", id);
+                self.stop_node(id);
+                churn.down_since[id] = Some(now);
+                churn.next[i].1 = now + churn.downtime.sample(&mut churn.rng);
+            } else {
+                println!("Churn: restarting node {}", id);
+                self.start_node(id);
+                nonces[id] = self.pending_nonce(id);
+                churn.downtime_total[id] += churn.down_since[id].take().unwrap().elapsed();
+                churn.restarts[id] += 1;
+                churn.next[i].1 = Instant::now() + churn.session.sample(&mut churn.rng);
+            }
+        }
+    }
+
+    // brings every churned node back up and reports how available each node was
+    pub(crate) fn finish_churn(&mut self, churn: Churn) -> Vec<Availability> {
+        let availability = churn.availability();
+        for (id, _) in churn.next {
+            if !self.is_running(id) {
+                self.start_node(id);
+            }
+        }
+        for a in availability.iter().filter(|a| a.restarts > 0 || a.downtime_ms > 0) {
+            println!(
+                "Churn: node {} restarted {} times, down for {}ms, available {:.1}%",
+                a.node, a.restarts, a.downtime_ms, a.availability * 100.0,
+            );
+        }
+        availability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dist() {
+        let mut rng = StdRng::seed_from_u64(1);
+        const N: usize = 20000;
+        let mean = |d: Dist, rng: &mut StdRng| {
+            (0..N).map(|_| d.sample(rng).as_secs_f64()).sum::<f64>() / N as f64
+        };
+        assert_eq!(Dist::Fixed(3.0).sample(&mut rng), Duration::from_secs(3));
+        assert!((mean(Dist::Uniform(5.0, 15.0), &mut rng) - 10.0).abs() < 0.2);
+        assert!((mean(Dist::Exponential(10.0), &mut rng) - 10.0).abs() < 0.5);
+        // mean of a pareto distribution is shape * scale / (shape - 1)
+        assert!((mean(Dist::Pareto { shape: 3.0, scale: 2.0 }, &mut rng) - 3.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_config() {
+        let cfg: Value = toml::from_str(r#"
+            [churn]
+            enabled = true
+            session = { dist = "exponential", mean = 60 }
+            downtime = { dist = "uniform", min = 5, max = 20.5 }
+        "#).unwrap();
+        let cfg = ChurnConfig::new_with_cfg(&cfg).unwrap();
+        assert_eq!(cfg.session, Dist::Exponential(60.0));
+        assert_eq!(cfg.downtime, Dist::Uniform(5.0, 20.5));
+        assert!(!cfg.include_sealers);
+    }
+}
@@ -0,0 +1,248 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::results::{self, RunSummary, SUMMARY_FILE};
+
+// two-sided 95% critical values of Student's t distribution for 1..=30 degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+fn t_critical(df: f64) -> f64 {
+    let df = df.floor() as usize;
+    match df {
+        0 => f64::INFINITY,
+        1..=30 => T_95[df-1],
+        31..=40 => 2.021,
+        41..=60 => 2.000,
+        61..=120 => 1.980,
+        _ => 1.960,
+    }
+}
+
+struct Metric {
+    name:           &'static str,
+    higher_better:  bool,
+    get:            fn(&RunSummary) -> f64,
+}
+
+const METRICS: [Metric; 6] = [
+    Metric { name: "TPS",                higher_better: true,  get: |s| s.chain.tps },
+    Metric { name: "confirmed txs",      higher_better: true,  get: |s| s.counts.confirmed as f64 },
+    Metric { name: "latency p50 (ms)",   higher_better: false, get: |s| s.latency_ms.p50 },
+    Metric { name: "latency p90 (ms)",   higher_better: false, get: |s| s.latency_ms.p90 },
+    Metric { name: "latency p99 (ms)",   higher_better: false, get: |s| s.latency_ms.p99 },
+    Metric { name: "block time (s)",     higher_better: false, get: |s| s.chain.block_interval.mean },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stat {
+    pub n:      usize,
+    pub mean:   f64,
+    pub var:    f64,
+    // half width of the 95% confidence interval of the mean
+    pub ci:     f64,
+}
+
+impl Stat {
+    pub fn from_samples(samples: &[f64]) -> Stat {
+        let n = samples.len();
+        let mean = samples.iter().sum::<f64>() / n.max(1) as f64;
+        let var = if n > 1 {
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64
+        } else {
+            0.0
+        };
+        let ci = if n > 1 {
+            t_critical((n - 1) as f64) * (var / n as f64).sqrt()
+        } else {
+            0.0
+        };
+        Stat { n, mean, var, ci }
+    }
+}
+
+// Welch's t-test needs at least two runs on each side
+pub fn enough_samples(a: &Stat, b: &Stat) -> bool {
+    a.n >= 2 && b.n >= 2
+}
+
+// Welch's t-test at the 5% level; without any variance on either side there is
+// nothing to test a difference against
+pub fn significant(a: &Stat, b: &Stat) -> bool {
+    if !enough_samples(a, b) {
+        return false;
+    }
+    let (va, vb) = (a.var / a.n as f64, b.var / b.n as f64);
+    let se = (va + vb).sqrt();
+    if se == 0.0 {
+        return false;
+    }
+    let t = (b.mean - a.mean) / se;
+    let df = (va + vb).powi(2) / (va.powi(2) / (a.n - 1) as f64 + vb.powi(2) / (b.n - 1) as f64);
+    t.abs() > t_critical(df)
+}
+
+pub struct RunGroup {
+    pub name:   String,
+    pub runs:   Vec<RunSummary>,
+}
+
+impl RunGroup {
+    // a directory holding `summary.json` is a single run, otherwise each of its
+    // subdirectories holding one is taken as a repetition of the same experiment
+    pub fn load(dir: &Path) -> io::Result<RunGroup> {
+        let mut runs = Vec::new();
+        if dir.join(SUMMARY_FILE).is_file() {
+            runs.push(results::load_summary(dir)?);
+        } else {
+            let mut subdirs: Vec<PathBuf> = fs::read_dir(dir)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<_>>()?;
+            subdirs.sort();
+            for sub in subdirs {
+                if sub.join(SUMMARY_FILE).is_file() {
+                    runs.push(results::load_summary(&sub)?);
+                }
+            }
+        }
+        if runs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no results found", dir.display()),
+            ));
+        }
+        Ok(RunGroup {
+            name: dir.display().to_string(),
+            runs,
+        })
+    }
+
+    fn stat(&self, metric: &Metric) -> Stat {
+        let samples: Vec<f64> = self.runs.iter().map(metric.get).collect();
+        Stat::from_samples(&samples)
+    }
+}
+
+struct Row {
+    metric:     &'static str,
+    base:       Stat,
+    other:      Stat,
+    verdict:    &'static str,
+}
+
+impl Row {
+    fn delta(&self) -> f64 {
+        self.other.mean - self.base.mean
+    }
+
+    fn delta_pct(&self) -> String {
+        if self.base.mean == 0.0 {
+            String::from("-")
+        } else {
+            format!("{:+.1}%", self.delta() / self.base.mean * 100.0)
+        }
+    }
+}
+
+fn compare_groups(base: &RunGroup, other: &RunGroup) -> Vec<Row> {
+    METRICS.iter().map(|m| {
+        let (b, o) = (base.stat(m), other.stat(m));
+        let verdict = if !enough_samples(&b, &o) {
+            "insufficient samples"
+        } else if !significant(&b, &o) {
+            ""
+        } else if (o.mean > b.mean) == m.higher_better {
+            "improvement"
+        } else {
+            "REGRESSION"
+        };
+        Row {
+            metric: m.name,
+            base:   b,
+            other:  o,
+            verdict,
+        }
+    }).collect()
+}
+
+fn fmt_stat(s: &Stat) -> String {
+    format!("{:.2} ± {:.2}", s.mean, s.ci)
+}
+
+// compares every group against the first one; returns whether a regression was found
+pub fn do_compare(dirs: &[PathBuf], markdown: bool) -> io::Result<bool> {
+    let groups = dirs.iter()
+        .map(|d| RunGroup::load(d))
+        .collect::<io::Result<Vec<_>>>()?;
+    let base = &groups[0];
+    let mut regressed = false;
+    for other in &groups[1..] {
+        let rows = compare_groups(base, other);
+        regressed |= rows.iter().any(|r| r.verdict == "REGRESSION");
+        if markdown {
+            print_markdown(base, other, &rows);
+        } else {
+            print_table(base, other, &rows);
+        }
+    }
+    Ok(regressed)
+}
+
+fn print_table(base: &RunGroup, other: &RunGroup, rows: &[Row]) {
+    println!("{} ({} runs) vs {} ({} runs)", base.name, base.runs.len(), other.name, other.runs.len());
+    println!("{:<18} {:>22} {:>22} {:>12} {:>8}", "metric", "baseline", "candidate", "delta", "delta%");
+    for r in rows {
+        println!(
+            "{:<18} {:>22} {:>22} {:>12.2} {:>8}  {}",
+            r.metric, fmt_stat(&r.base), fmt_stat(&r.other), r.delta(), r.delta_pct(), r.verdict,
+        );
+    }
+    println!();
+}
+
+fn print_markdown(base: &RunGroup, other: &RunGroup, rows: &[Row]) {
+    println!("### `{}` ({} runs) vs `{}` ({} runs)", base.name, base.runs.len(), other.name, other.runs.len());
+    println!();
+    println!("| metric | baseline | candidate | delta | delta % | |");
+    println!("|---|---:|---:|---:|---:|---|");
+    for r in rows {
+        println!(
+            "| {} | {} | {} | {:.2} | {} | {} |",
+            r.metric, fmt_stat(&r.base), fmt_stat(&r.other), r.delta(), r.delta_pct(), r.verdict,
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stat() {
+        let s = Stat::from_samples(&[1.0, 2.0, 3.0]);
+        assert_eq!(s.mean, 2.0);
+        assert_eq!(s.var, 1.0);
+        assert!((s.ci - 4.303 / 3f64.sqrt()).abs() < 1e-9);
+        assert_eq!(Stat::from_samples(&[5.0]).ci, 0.0);
+    }
+
+    #[test]
+    fn test_significant() {
+        let a = Stat::from_samples(&[100.0, 101.0, 99.0, 100.5]);
+        let b = Stat::from_samples(&[80.0, 81.0, 79.0, 80.5]);
+        let c = Stat::from_samples(&[100.2, 99.1, 101.3, 99.9]);
+        assert!(significant(&a, &b));
+        assert!(!significant(&a, &c));
+        // single runs cannot be tested
+        assert!(!significant(&Stat::from_samples(&[1.0]), &Stat::from_samples(&[2.0])));
+        assert!(!enough_samples(&Stat::from_samples(&[1.0]), &a));
+        // identical runs on both sides leave no variance to test against
+        assert!(!significant(&Stat::from_samples(&[10.0, 10.0]), &Stat::from_samples(&[11.0, 11.0])));
+    }
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use toml::Value;
+
+use crate::sweep::{self, Cell};
+use crate::utils;
+
+// a config file with the values given on the command line on top
+pub fn load(path: &Path, overrides: &Cell) -> Value {
+    sweep::apply(&utils::read_toml(path), overrides)
+}
+
+// `key=value` with a dotted key, the value is read as toml and taken as a plain
+// string if it is not valid toml, e.g. `test.rate=2.0` or `node.dir=nodes2`
+pub fn parse_override(s: &str) -> Result<(String, Value), String> {
+    let (key, raw) = s.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))?;
+    let value = match format!("v = {}", raw).parse::<Value>() {
+        Ok(v) => v["v"].clone(),
+        Err(_) => Value::String(String::from(raw)),
+    };
+    Ok((String::from(key.trim()), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_override() {
+        assert_eq!(parse_override("test.rate=2.0").unwrap(), (String::from("test.rate"), Value::Float(2.0)));
+        assert_eq!(parse_override("node.count=10").unwrap().1, Value::Integer(10));
+        assert_eq!(parse_override("verify.enabled=false").unwrap().1, Value::Boolean(false));
+        assert_eq!(parse_override("node.dir=nodes2").unwrap().1, Value::from("nodes2"));
+        assert_eq!(parse_override("node.connection=[[1], [0]]").unwrap().1.as_array().unwrap().len(), 2);
+        assert!(parse_override("node.count").is_err());
+    }
+}
@@ -0,0 +1,424 @@
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use serde_derive::Serialize;
+use toml::Value;
+
+use crate::attach::{rpc_request, NodeConsole};
+use crate::init::NodeInitializer;
+use crate::run::NodeRunner;
+use crate::state::RunState;
+use crate::utils;
+use crate::verify::{self, VerifyConfig};
+use crate::wait::{wait_until, POLL_INTERVAL};
+
+// bytes of a node log searched for the last lines
+const LOG_TAIL: u64 = 1024 * 1024;
+
+fn nodes_dir(cfg: &Value) -> PathBuf {
+    PathBuf::from(cfg["node"]["dir"].as_str().unwrap())
+}
+
+// asks for a yes on stdin
+pub fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).unwrap();
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+// processes of the run state that are still alive, the runner first
+pub(crate) fn live_pids(state: &RunState) -> Vec<u32> {
+    std::iter::once(state.runner_pid)
+        .chain(state.nodes.iter().filter_map(|n| n.pid))
+        .filter(|&pid| utils::pid_alive(pid))
+        .collect()
+}
+
+// stops the runner, whose nodes exit once their console closes, and kills the
+// nodes that are still there after `timeout`
+pub fn stop(cfg: &Value, timeout: Duration) -> bool {
+    let nodes_dir = nodes_dir(cfg);
+    let state = match RunState::load(&nodes_dir) {
+        Ok(state) => state,
+        Err(_) => {
+            println!("No run state in {}, nothing to stop", nodes_dir.display());
+            return true;
+        },
+    };
+    if utils::pid_alive(state.runner_pid) {
+        println!("Stopping runner {}", state.runner_pid);
+        utils::terminate(state.runner_pid, false);
+    }
+    // nodes added by `add-node` do not go away with the runner
+    for n in state.nodes.iter().filter(|n| n.detached) {
+        if let Some(pid) = n.pid.filter(|&pid| utils::pid_alive(pid)) {
+            utils::terminate(pid, false);
+        }
+    }
+    let nodes: Vec<(usize, u32)> = state.nodes.iter().filter_map(|n| n.pid.map(|pid| (n.id, pid))).collect();
+    let stopped = wait_until(timeout, POLL_INTERVAL, || nodes.iter().all(|&(_, pid)| !utils::pid_alive(pid)));
+    if !stopped {
+        for &(id, pid) in nodes.iter().filter(|&&(_, pid)| utils::pid_alive(pid)) {
+            println!("Node {} did not exit within {}s, killing {}", id, timeout.as_secs(), pid);
+            utils::terminate(pid, true);
+        }
+    }
+    RunState::update(&nodes_dir, |state| {
+        for n in &mut state.nodes {
+            n.running = false;
+            n.pid = None;
+        }
+    }).expect("Write run state failed");
+    println!("Stopped {} node(s)", nodes.len());
+    true
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct NodeStatus {
+    pub id:         usize,
+    pub role:       String,
+    pub pid:        Option<u32>,
+    pub status:     String,
+    pub address:    Option<String>,
+    pub enode:      Option<String>,
+    pub head:       Option<u64>,
+    pub peers:      Option<u64>,
+    pub syncing:    Option<bool>,
+    pub mining:     Option<bool>,
+    pub healthy:    bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NetworkStatus {
+    pub runner_pid:     u32,
+    pub runner_alive:   bool,
+    pub healthy:        bool,
+    pub nodes:          Vec<NodeStatus>,
+}
+
+fn rpc_result(endpoint: &str, method: &str) -> io::Result<serde_json::Value> {
+    rpc_request(endpoint, method, serde_json::json!([]))
+}
+
+// fills in what the node reports about itself over its ipc endpoint
+fn probe(endpoint: &str, status: &mut NodeStatus) -> io::Result<()> {
+    status.head = Some(utils::json_u64(&rpc_result(endpoint, "eth_blockNumber")?));
+    status.peers = Some(utils::json_u64(&rpc_result(endpoint, "net_peerCount")?));
+    // false when in sync, an object with the progress otherwise
+    status.syncing = Some(rpc_result(endpoint, "eth_syncing")? != serde_json::Value::Bool(false));
+    status.mining = rpc_result(endpoint, "eth_mining")?.as_bool();
+    status.enode = rpc_result(endpoint, "admin_nodeInfo")?["enode"].as_str().map(String::from);
+    Ok(())
+}
+
+// the nodes of the run state, whether their processes are alive and what they
+// answer over ipc, a node is healthy when both hold
+pub fn network_status(cfg: &Value) -> io::Result<NetworkStatus> {
+    let nodes_dir = nodes_dir(cfg);
+    let state = RunState::load(&nodes_dir)?;
+    let addrs = utils::load_addrs(Path::new(cfg["run"]["accounts_dir"].as_str().unwrap())).unwrap_or_default();
+    let runner_alive = utils::pid_alive(state.runner_pid);
+    let nodes: Vec<NodeStatus> = state.nodes.iter().map(|n| {
+        let mut status = NodeStatus {
+            id:         n.id,
+            role:       String::from(if n.sealer { "sealer" } else { "peer" }),
+            pid:        n.pid,
+            address:    addrs.get(n.id).map(|a| format!("0x{}", a)),
+            enode:      n.enode.clone(),
+            ..Default::default()
+        };
+        let alive = n.pid.is_some_and(utils::pid_alive);
+        status.status = String::from(if !alive {
+            if n.running { "dead" } else { "stopped" }
+        } else if probe(&n.ipc, &mut status).is_err() {
+            "unreachable"
+        } else {
+            "up"
+        });
+        status.healthy = status.status == "up";
+        status
+    }).collect();
+    Ok(NetworkStatus {
+        runner_pid: state.runner_pid,
+        runner_alive,
+        healthy:    runner_alive && nodes.iter().all(|n| n.healthy),
+        nodes,
+    })
+}
+
+fn or_dash<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or(String::from("-"), |v| v.to_string())
+}
+
+// prints the status of a running network as a table or json, returns whether
+// it is healthy
+pub fn status(cfg: &Value, json: bool) -> bool {
+    let status = match network_status(cfg) {
+        Ok(status) => status,
+        Err(_) => {
+            println!("No run state in {}, the network was never run", nodes_dir(cfg).display());
+            return false;
+        },
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+        return status.healthy;
+    }
+    println!("runner {} {}", status.runner_pid, if status.runner_alive { "alive" } else { "gone" });
+    println!(
+        "{:>4}  {:<6}  {:>7}  {:<11}  {:<42}  {:>8}  {:>5}  {:<7}  {:<6}  ENODE",
+        "NODE", "ROLE", "PID", "STATUS", "ADDRESS", "HEAD", "PEERS", "SYNCING", "MINING",
+    );
+    for n in &status.nodes {
+        println!(
+            "{:>4}  {:<6}  {:>7}  {:<11}  {:<42}  {:>8}  {:>5}  {:<7}  {:<6}  {}",
+            n.id,
+            n.role,
+            or_dash(&n.pid),
+            n.status,
+            or_dash(&n.address),
+            or_dash(&n.head),
+            or_dash(&n.peers),
+            or_dash(&n.syncing),
+            or_dash(&n.mining),
+            or_dash(&n.enode),
+        );
+    }
+    let unhealthy = status.nodes.iter().filter(|n| !n.healthy).count();
+    if unhealthy > 0 {
+        println!("{} of {} node(s) unhealthy", unhealthy, status.nodes.len());
+    }
+    status.healthy
+}
+
+// files of a datadir kept when only the chain is reset, the keystore sits
+// beside geth/ and is kept as a whole
+const KEPT_FILES: &[&str] = &["nodekey"];
+
+// what `clean` removes: everything, or the datadirs of `nodes` if any, and with
+// `chain_only` just the chain data so that keys and addresses stay the same
+pub fn clean_targets(cfg: &Value, nodes: &[usize], chain_only: bool) -> Vec<PathBuf> {
+    let nodes_dir = nodes_dir(cfg);
+    let ids: Vec<usize> = if nodes.is_empty() {
+        (0..cfg["node"]["count"].as_integer().unwrap() as usize).collect()
+    } else {
+        nodes.to_vec()
+    };
+    let mut targets = Vec::new();
+    if chain_only {
+        for &id in &ids {
+            let geth = Path::new(&utils::node_dir(&nodes_dir, id)).join("geth");
+            if let Ok(entries) = fs::read_dir(&geth) {
+                let mut entries: Vec<PathBuf> = entries
+                    .map(|e| e.unwrap())
+                    .filter(|e| !KEPT_FILES.contains(&e.file_name().to_str().unwrap()))
+                    .map(|e| e.path())
+                    .collect();
+                entries.sort();
+                targets.extend(entries);
+            }
+        }
+        // the run state of a network that is gone only misleads status and attach
+        if nodes.is_empty() {
+            targets.push(RunState::path(&nodes_dir));
+        }
+    } else if !nodes.is_empty() {
+        targets.extend(ids.iter().map(|&id| nodes_dir.join(format!("node{}", id))));
+    } else {
+        targets.push(nodes_dir.clone());
+        let accounts = PathBuf::from(cfg["init"]["accounts_dir"].as_str().unwrap());
+        if !accounts.starts_with(&nodes_dir) {
+            targets.push(accounts);
+        }
+        // puppeth would offer to rewrite the old genesis on the next init
+        targets.push(NodeInitializer::new_with_cfg(cfg).puppeth_network());
+    }
+    targets.retain(|p| p.exists());
+    targets
+}
+
+// removes what `clean_targets` finds, refusing while any process of the network
+// is alive
+pub fn clean(cfg: &Value, nodes: &[usize], chain_only: bool, yes: bool) -> bool {
+    let nodes_dir = nodes_dir(cfg);
+    if let Ok(state) = RunState::load(&nodes_dir) {
+        let live = live_pids(&state);
+        if !live.is_empty() {
+            println!("Processes {:?} of the network are still running, use `stop` first", live);
+            return false;
+        }
+    }
+    let targets = clean_targets(cfg, nodes, chain_only);
+    if targets.is_empty() {
+        println!("Nothing to clean in {}", nodes_dir.display());
+        return true;
+    }
+    for path in &targets {
+        println!("  {}", path.display());
+    }
+    let what = if chain_only { "the chain data above, keeping keys and accounts" } else { "the above with all accounts and chain data" };
+    if !yes && !confirm(&format!("Remove {}?", what)) {
+        return false;
+    }
+    for path in &targets {
+        if path.is_dir() {
+            fs::remove_dir_all(path).unwrap();
+        } else {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    println!("Removed {} path(s)", targets.len());
+    if chain_only || !nodes.is_empty() {
+        println!("Run `init` to initialize the genesis again");
+    }
+    true
+}
+
+// prints the last lines of the geth log of a node, then what it appends if `follow`
+pub fn logs(cfg: &Value, id: usize, lines: usize, follow: bool) -> bool {
+    let path = NodeRunner::log_path(&nodes_dir(cfg), id);
+    if !path.exists() {
+        println!("No log at {}, logs are written when running with --node-logs or the dashboard", path.display());
+        return false;
+    }
+    let tail = utils::tail(&path, LOG_TAIL);
+    for line in &tail[tail.len().saturating_sub(lines)..] {
+        println!("{}", line);
+    }
+    if follow {
+        follow_file(&path);
+    }
+    true
+}
+
+fn follow_file(path: &Path) {
+    let mut file = File::open(path).unwrap();
+    let mut pos = file.seek(SeekFrom::End(0)).unwrap();
+    let mut out = io::stdout();
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let len = fs::metadata(path).map_or(0, |m| m.len());
+        // the log starts over when the datadir was cleaned
+        if len < pos {
+            file = File::open(path).unwrap();
+            pos = 0;
+        }
+        file.seek(SeekFrom::Start(pos)).unwrap();
+        let mut buf = Vec::new();
+        pos += file.read_to_end(&mut buf).unwrap() as u64;
+        out.write_all(&buf).unwrap();
+        out.flush().unwrap();
+    }
+}
+
+// the peerings the runner would dial, as a list or a graphviz digraph
+pub fn topology(cfg: &Value, dot: bool) {
+    let count = cfg["node"]["count"].as_integer().unwrap() as usize;
+    let sealers = cfg["node"]["sealer_count"].as_integer().unwrap() as usize;
+    let seed = cfg["node"].get("seed").map(|v| v.as_integer().unwrap() as u64);
+    let random = cfg["node"].get("random_connect").is_some_and(|v| v.as_bool().unwrap());
+    // an unseeded random topology differs on every run, so the sampled seed is shown
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        if random && !dot {
+            println!("Unseeded random topology, this one has seed {} (--set node.seed={})", seed, seed);
+        }
+        seed
+    });
+    let topology = NodeRunner::build_topology(cfg, count, seed);
+    if dot {
+        println!("digraph network {{");
+        for id in 0..sealers {
+            println!("    {} [shape=box];", id);
+        }
+        for (x, peers) in topology.iter().enumerate() {
+            for y in peers {
+                println!("    {} -> {};", x, y);
+            }
+        }
+        println!("}}");
+    } else {
+        for (x, peers) in topology.iter().enumerate() {
+            let peers: Vec<String> = peers.iter().map(|p| p.to_string()).collect();
+            println!("node {:<4} {:<6}  -> {}", x, if x < sealers { "sealer" } else { "peer" }, peers.join(", "));
+        }
+    }
+}
+
+// the checks run after a test, against a network run by another process
+pub fn verify(cfg: &Value, converge_timeout: Duration, samples: usize) -> bool {
+    let mut nc = NodeConsole::new_with_cfg(cfg);
+    verify::verify_chain(&mut nc, &VerifyConfig { converge_timeout, samples }).converged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_probe() {
+        let (endpoint, server) = crate::attach::fake_ipc("status", 5, |req| {
+            match req["method"].as_str().unwrap() {
+                "eth_blockNumber" => serde_json::json!("0x10"),
+                "net_peerCount" => serde_json::json!("0x2"),
+                "eth_syncing" => serde_json::json!({"currentBlock": "0x10", "highestBlock": "0x20"}),
+                "eth_mining" => serde_json::json!(true),
+                "admin_nodeInfo" => serde_json::json!({"enode": "enode://ab@127.0.0.1:3000"}),
+                m => panic!("Unexpected method {}", m),
+            }
+        });
+        let mut status = NodeStatus::default();
+        probe(endpoint.to_str().unwrap(), &mut status).unwrap();
+        server.join().unwrap();
+        assert_eq!(status.head, Some(16));
+        assert_eq!(status.peers, Some(2));
+        assert_eq!(status.syncing, Some(true));
+        assert_eq!(status.mining, Some(true));
+        assert_eq!(status.enode.as_deref(), Some("enode://ab@127.0.0.1:3000"));
+        fs::remove_file(&endpoint).unwrap();
+    }
+
+    #[test]
+    fn test_clean_targets() {
+        let dir = std::env::temp_dir().join(format!("ethrunner-clean-{}", std::process::id()));
+        let nodes_dir = dir.join("nodes");
+        for id in 0..2 {
+            let data = PathBuf::from(utils::node_dir(&nodes_dir, id));
+            fs::create_dir_all(data.join("keystore")).unwrap();
+            fs::create_dir_all(data.join("geth/chaindata")).unwrap();
+            File::create(data.join("geth/nodekey")).unwrap();
+            File::create(data.join("geth/LOCK")).unwrap();
+        }
+        File::create(RunState::path(&nodes_dir)).unwrap();
+        let cfg: Value = toml::from_str(&format!(r#"
+            [bin]
+            geth_dir = "geth"
+            puppeth_dir = "puppeth"
+            [node]
+            dir = "{nodes}"
+            count = 2
+            sealer_count = 1
+            [init]
+            accounts_dir = "{nodes}/accounts.toml"
+            puppeth_home = "{home}"
+        "#, nodes = nodes_dir.display(), home = dir.display())).unwrap();
+
+        assert_eq!(clean_targets(&cfg, &[], false), vec![nodes_dir.clone()]);
+        assert_eq!(clean_targets(&cfg, &[1], false), vec![nodes_dir.join("node1")]);
+
+        let geth1 = Path::new(&utils::node_dir(&nodes_dir, 1)).join("geth");
+        assert_eq!(clean_targets(&cfg, &[1], true), vec![geth1.join("LOCK"), geth1.join("chaindata")]);
+        let chain = clean_targets(&cfg, &[], true);
+        assert_eq!(chain.len(), 5);
+        assert!(chain.contains(&RunState::path(&nodes_dir)));
+        assert!(chain.iter().all(|p| !p.ends_with("nodekey") && !p.ends_with("keystore")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+
+use crate::metrics::MetricsState;
+use crate::run::NodeRunner;
+use crate::utils;
+
+// how long to wait for a key before the screen is redrawn
+const REFRESH: Duration = Duration::from_millis(500);
+// bytes read from the end of a node log to fill the log pane
+const LOG_TAIL: u64 = 64 * 1024;
+// window of block timestamps the throughput is averaged over
+const TPS_WINDOW: u64 = 60;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const KEYS: &str = "↑/↓ select  l log  s stop/start  m mining  q quit";
+
+#[derive(Debug, Default)]
+struct View {
+    selected:   usize,
+    show_log:   bool,
+    status:     String,
+}
+
+enum Action {
+    Quit,
+    ToggleNode(usize),
+    ToggleMining(usize),
+}
+
+// restores the terminal however the dashboard is left, panics included
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// the last `width` values as bars scaled to the largest one
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    let values = &values[values.len().saturating_sub(width)..];
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    values.iter()
+        .map(|&v| {
+            if max <= 0.0 {
+                BARS[0]
+            } else {
+                BARS[((v / max) * (BARS.len() - 1) as f64).round() as usize]
+            }
+        })
+        .collect()
+}
+
+// transactions per second over the blocks of the last window, and the mean block time
+fn throughput(state: &MetricsState) -> (f64, f64) {
+    let last = match state.blocks.back() {
+        Some(b) => b,
+        None => return (0.0, 0.0),
+    };
+    let window: Vec<_> = state.blocks.iter().filter(|b| b.timestamp + TPS_WINDOW > last.timestamp).collect();
+    let first = window[0];
+    let span = (last.timestamp - first.timestamp) as f64;
+    if span == 0.0 {
+        return (0.0, 0.0);
+    }
+    // the transactions of the first block were sent before the window began
+    let txs: usize = window[1..].iter().map(|b| b.txs).sum();
+    (txs as f64 / span, span / (window.len() - 1) as f64)
+}
+
+fn render(state: &MetricsState, view: &View, width: usize, height: usize, log: &[String]) -> Vec<String> {
+    let head = state.nodes.iter().map(|n| n.head).max().unwrap_or(0);
+    let (tps, block_time) = throughput(state);
+    let mut lines = vec![
+        format!(
+            "ethrunner  {} nodes  head #{}  {:.1} tx/s  {:.1}s/block  sent {} confirmed {} failed {}",
+            state.nodes.len(), head, tps, block_time, state.submitted, state.confirmed, state.failed,
+        ),
+        format!("  {:>4}  {:<6}  {:>7}  {:<7}  {:>7}  {:>5}  {:>9}  {:<6}  {}", "NODE", "ROLE", "PID", "STATUS", "HEAD", "PEERS", "TXPOOL", "MINING", "LAST ERROR"),
+    ];
+    for (id, n) in state.nodes.iter().enumerate() {
+        lines.push(format!(
+            "{} {:>4}  {:<6}  {:>7}  {:<7}  {:>7}  {:>5}  {:>9}  {:<6}  {}",
+            if id == view.selected { '>' } else { ' ' },
+            id,
+            if n.sealer { "sealer" } else { "peer" },
+            n.pid.map_or(String::from("-"), |p| p.to_string()),
+            if n.up { "up" } else { "down" },
+            n.head,
+            n.peers,
+            format!("{}/{}", n.pending, n.queued),
+            if n.mining { "yes" } else { "no" },
+            n.last_error.as_deref().unwrap_or("-"),
+        ));
+    }
+    let chart = width.saturating_sub(14);
+    let txs: Vec<f64> = state.blocks.iter().map(|b| b.txs as f64).collect();
+    let times: Vec<f64> = state.blocks.iter().zip(state.blocks.iter().skip(1))
+        .map(|(a, b)| (b.timestamp - a.timestamp) as f64)
+        .collect();
+    lines.push(String::new());
+    lines.push(format!("txs/block    {}", sparkline(&txs, chart)));
+    lines.push(format!("block time   {}", sparkline(&times, chart)));
+    lines.push(String::new());
+    lines.push(format!("{}  {}", KEYS, view.status));
+    if view.show_log {
+        lines.push(format!("--- log of node {} ---", view.selected));
+        let room = height.saturating_sub(lines.len());
+        lines.extend(log[log.len().saturating_sub(room)..].iter().cloned());
+    }
+    lines.truncate(height);
+    lines.iter().map(|l| l.chars().take(width).collect()).collect()
+}
+
+fn draw(lines: &[String]) -> io::Result<()> {
+    let mut out = io::stdout();
+    queue!(out, terminal::Clear(ClearType::All))?;
+    for (i, line) in lines.iter().enumerate() {
+        queue!(out, cursor::MoveTo(0, i as u16), Print(line))?;
+    }
+    out.flush()
+}
+
+impl NodeRunner {
+    // runs the network and shows it full screen until `q` is pressed, the nodes are
+    // stopped on the way out
+    pub fn do_dashboard(mut self) {
+        utils::set_console_echo(false);
+        self.set_node_logs(true);
+        self.track_metrics();
+        println!("Starting {} nodes, geth logs go to geth.log in each datadir", self.node_count());
+        self.start_network();
+        self.start_mining();
+
+        let screen = Screen::enter().expect("Enter dashboard failed");
+        let mut view = View::default();
+        loop {
+            self.redraw(&view);
+            if !event::poll(REFRESH).unwrap() {
+                continue;
+            }
+            let key = match event::read().unwrap() {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            let action = match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => Some(Action::Quit),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Action::Quit),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    view.selected = view.selected.saturating_sub(1);
+                    None
+                },
+                KeyCode::Down | KeyCode::Char('j') => {
+                    view.selected = (view.selected + 1).min(self.node_count() - 1);
+                    None
+                },
+                KeyCode::Char('l') => {
+                    view.show_log = !view.show_log;
+                    None
+                },
+                KeyCode::Char('s') => Some(Action::ToggleNode(view.selected)),
+                KeyCode::Char('m') => Some(Action::ToggleMining(view.selected)),
+                _ => None,
+            };
+            match action {
+                Some(Action::Quit) => break,
+                Some(Action::ToggleNode(id)) => {
+                    let running = self.is_running(id);
+                    view.status = format!("{} node {}...", if running { "stopping" } else { "starting" }, id);
+                    self.redraw(&view);
+                    if running {
+                        self.stop_node(id);
+                    } else {
+                        self.start_node(id);
+                    }
+                    view.status = format!("node {} {}", id, if running { "stopped" } else { "started" });
+                },
+                Some(Action::ToggleMining(id)) if self.is_running(id) => {
+                    let on = !self.is_mining(id);
+                    self.set_mining(id, on);
+                    view.status = format!("{} mining on node {}", if on { "started" } else { "stopped" }, id);
+                },
+                Some(Action::ToggleMining(id)) => view.status = format!("node {} is not running", id),
+                None => (),
+            }
+        }
+        drop(screen);
+        utils::set_console_echo(true);
+        println!("Stopping nodes");
+        self.stop_nodes();
+    }
+
+    fn redraw(&mut self, view: &View) {
+        self.update_metrics();
+        let (width, height) = terminal::size().unwrap();
+        let log = if view.show_log { utils::tail(&NodeRunner::log_path(self.nodes_dir(), view.selected), LOG_TAIL) } else { Vec::new() };
+        let state = self.track_metrics().state.clone();
+        let lines = render(&state.lock().unwrap(), view, width as usize, height as usize, &log);
+        draw(&lines).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::BlockSample;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0.0, 1.0, 2.0, 4.0], 10), "▁▃▅█");
+        assert_eq!(sparkline(&[9.0, 0.0, 0.0], 2), "▁▁");
+        assert_eq!(sparkline(&[], 5), "");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut state = MetricsState::new(2, 1);
+        state.nodes[0].up = true;
+        state.nodes[0].pid = Some(42);
+        state.nodes[0].head = 3;
+        state.nodes[1].last_error = Some(String::from("Error: nonce too low"));
+        for (timestamp, txs) in [(100, 0), (105, 10), (110, 20)] {
+            state.push_block(BlockSample { timestamp, txs });
+        }
+        let view = View { selected: 1, show_log: true, status: String::new() };
+        let log: Vec<String> = (0..50).map(|i| format!("line {}", i)).collect();
+        let lines = render(&state, &view, 100, 12, &log);
+
+        assert_eq!(lines.len(), 12);
+        assert!(lines[0].contains("head #3") && lines[0].contains("3.0 tx/s") && lines[0].contains("5.0s/block"));
+        assert!(lines[2].starts_with("     0  sealer       42  up"));
+        assert!(lines[3].starts_with(">    1  peer          -  down"));
+        assert!(lines[3].ends_with("Error: nonce too low"));
+        assert_eq!(lines[9], "--- log of node 1 ---");
+        assert_eq!(lines[11], "line 49");
+        assert!(render(&state, &view, 20, 12, &log).iter().all(|l| l.chars().count() <= 20));
+    }
+}
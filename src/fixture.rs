@@ -0,0 +1,147 @@
+use std::env;
+use std::fs;
+use std::net::{TcpListener, UdpSocket};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::network::{Network, NetworkBuilder, Topology};
+
+// p2p ports handed out to fixtures, the first block depends on the process so that
+// test binaries running side by side start apart
+const PORT_MIN: u16 = 20000;
+const PORT_MAX: u16 = 60000;
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+static NEXT_PORT: AtomicU16 = AtomicU16::new(0);
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+fn is_free(port: u16) -> bool {
+    TcpListener::bind(("0.0.0.0", port)).is_ok() && UdpSocket::bind(("0.0.0.0", port)).is_ok()
+}
+
+// `count` consecutive ports free right now, never handed out twice by this process
+pub fn reserve_ports(count: usize) -> u16 {
+    let count = count as u16;
+    let _ = NEXT_PORT.compare_exchange(0, PORT_MIN + (std::process::id() % 200) as u16 * 100, Ordering::SeqCst, Ordering::SeqCst);
+    loop {
+        let base = NEXT_PORT.fetch_add(count, Ordering::SeqCst);
+        if base.checked_add(count).is_none_or(|end| end > PORT_MAX) {
+            // wraps around, whoever gets PORT_MIN starts over; the counter itself may
+            // have overflowed, as fetch_add wraps, and the next block is range checked
+            // again in case another thread moved it meanwhile
+            let _ = NEXT_PORT.compare_exchange(base.wrapping_add(count), PORT_MIN, Ordering::SeqCst, Ordering::SeqCst);
+            continue;
+        }
+        if (base..base + count).all(is_free) {
+            return base;
+        }
+    }
+}
+
+// an initialized and started network in a directory of its own under the temp dir,
+// its nodes are stopped and the directory removed on drop, e.g.
+//
+//     let mut net = TestNetwork::start(4, 2);
+//     let hash = net.node(0).eval("eth.sendTransaction({...})");
+//     assert!(net.wait_block(3, Duration::from_secs(30)));
+//
+// geth and puppeth are taken from ETHRUNNER_GETH and ETHRUNNER_PUPPETH, or PATH
+pub struct TestNetwork {
+    net:    Network,
+    dir:    PathBuf,
+}
+
+impl TestNetwork {
+    // `nodes` nodes in a ring, the first `sealers` of them sealing a block per second
+    pub fn start(nodes: usize, sealers: usize) -> TestNetwork {
+        TestNetwork::start_with(nodes, sealers, |b| b)
+    }
+
+    // as `start`, with further settings applied to the builder
+    pub fn start_with<F>(nodes: usize, sealers: usize, f: F) -> TestNetwork
+        where F: FnOnce(NetworkBuilder) -> NetworkBuilder
+    {
+        let dir = env::temp_dir().join(format!("ethrunner-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::SeqCst)));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let ring = if nodes > 1 { (0..nodes).map(|i| vec![(i + 1) % nodes]).collect() } else { vec![Vec::new()] };
+        let builder = Network::builder(&dir)
+            .geth(env::var("ETHRUNNER_GETH").unwrap_or_else(|_| String::from("geth")))
+            .puppeth(env::var("ETHRUNNER_PUPPETH").unwrap_or_else(|_| String::from("puppeth")))
+            .nodes(nodes, sealers)
+            .topology(Topology::Explicit(ring))
+            .block_period(1)
+            .base_port(reserve_ports(nodes))
+            .set("init.puppeth_home", dir.display().to_string());
+        let mut net = TestNetwork {
+            net:    f(builder).build().unwrap(),
+            dir,
+        };
+        net.init().expect("Init of the test network failed");
+        net.start().expect("Start of the test network failed");
+        if sealers > 0 && !net.wait_block(1, READY_TIMEOUT) {
+            panic!("Test network sealed no block within {}s", READY_TIMEOUT.as_secs());
+        }
+        net
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Deref for TestNetwork {
+    type Target = Network;
+
+    fn deref(&self) -> &Network {
+        &self.net
+    }
+}
+
+impl DerefMut for TestNetwork {
+    fn deref_mut(&mut self) -> &mut Network {
+        &mut self.net
+    }
+}
+
+impl Drop for TestNetwork {
+    fn drop(&mut self) {
+        self.net.shutdown();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_ports() {
+        let handles: Vec<_> = (0..4).map(|_| std::thread::spawn(|| reserve_ports(10))).collect();
+        let mut bases: Vec<u16> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        bases.sort();
+        for w in bases.windows(2) {
+            assert!(w[1] >= w[0] + 10);
+        }
+        assert!(bases.iter().all(|&b| (PORT_MIN..=PORT_MAX - 10).contains(&b)));
+    }
+
+    // needs geth and puppeth, run with ETHRUNNER_GETH=... cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn test_network_tx() {
+        if env::var("ETHRUNNER_GETH").is_err() {
+            return;
+        }
+        let mut net = TestNetwork::start(8, 2);
+        let to = net.node(1).address();
+        let hash = net.node(0).eval(&format!("eth.sendTransaction({{from: eth.accounts[0], to: \"0x{}\", value: 1}})", to));
+        assert!(crate::results::is_tx_hash(&hash), "sendTransaction answered {}", hash);
+        // the transaction travels from the first node of the network to the last
+        let receipt = net.node(7).wait_receipt(&hash, Duration::from_secs(60));
+        assert!(receipt.is_some(), "node 7 has no receipt of {}", hash);
+    }
+}
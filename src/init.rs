@@ -1,30 +1,15 @@
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::io::prelude::*;
 use std::str::FromStr;
-use std::error::Error;
-use std::fmt;
 use std::process::{Command, Stdio};
 use std::env;
 
+use toml::Value;
+
 use crate::utils::{self, Console, ConsoleInteractor, node_dir};
 use crate::{Address, NETWORK, NETWORK_ID};
 
-struct InitError;
-
-impl fmt::Display for InitError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-impl fmt::Debug for InitError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-impl Error for InitError {}
-
 #[derive(Debug)]
 pub struct NodeInitializer {
     geth_dir:       PathBuf,
@@ -32,55 +17,232 @@ pub struct NodeInitializer {
     nodes_dir:       PathBuf,
     node_count:     usize,
     sealer_count:   usize,
+    block_period:   Option<u64>,
+    // HOME of puppeth, which keeps its networks in HOME/.puppeth
+    puppeth_home:   Option<PathBuf>,
     out:            PathBuf,
+    // replace a genesis that differs from the requested one, with the chain data
+    force:          bool,
 }
 
 impl NodeInitializer {
     pub fn new_with_cfg_file(path: &Path) -> NodeInitializer {
-        let parsed = utils::read_toml(path);
+        NodeInitializer::new_with_cfg(&utils::read_toml(path))
+    }
+
+    pub fn new_with_cfg(parsed: &Value) -> NodeInitializer {
         NodeInitializer {
             geth_dir:       PathBuf::from_str(parsed["bin"]["geth_dir"].as_str().unwrap()).unwrap(),
             puppeth_dir:    PathBuf::from_str(parsed["bin"]["puppeth_dir"].as_str().unwrap()).unwrap(),
             nodes_dir:      PathBuf::from_str(parsed["node"]["dir"].as_str().unwrap()).unwrap(),
             node_count:     parsed["node"]["count"].as_integer().unwrap() as usize,
             sealer_count:   parsed["node"]["sealer_count"].as_integer().unwrap() as usize,
+            block_period:   parsed["init"].get("block_period").map(|v| v.as_integer().unwrap() as u64),
+            puppeth_home:   parsed["init"].get("puppeth_home").map(|v| PathBuf::from(v.as_str().unwrap())),
             out:            PathBuf::from_str(parsed["init"]["accounts_dir"].as_str().unwrap()).unwrap(),
+            force:          false,
         }
     }
 
-    pub fn do_init_node(&self) {
+    pub fn set_force(&mut self, force: bool) {
+        self.force = force;
+    }
+
+    // skips what an earlier, possibly interrupted, init already did: accounts
+    // found in the keystores are reused, a matching genesis is kept and nodes
+    // initialized with it are left alone; returns false when the existing genesis
+    // differs from the requested one and `force` is not set
+    pub fn do_init_node(&self) -> bool {
         let accounts = self.create_accounts();
-        self.create_genesis(&accounts);
-        self.init_nodes();
+        self.write_password();
+        let genesis_path = self.genesis_path();
+        match self.read_genesis() {
+            None => self.create_genesis(&accounts),
+            Some(genesis) => match self.genesis_diff(&genesis, &accounts) {
+                None => println!("Genesis {} is up to date", genesis_path),
+                Some(diff) if !self.force => {
+                    println!("Existing genesis {} differs from the requested one: {}", genesis_path, diff);
+                    println!("Use --force to replace it and reset the chain data of every node");
+                    return false;
+                },
+                Some(diff) => {
+                    println!("Replacing genesis {}: {}", genesis_path, diff);
+                    self.create_genesis(&accounts);
+                },
+            },
+        }
+        self.init_nodes()
     }
 
-    fn init_nodes(&self) {
+    // what `do_init_node` would run, puppeth is driven through its prompts
+    pub fn print_commands(&self) {
+        let saved = utils::load_addrs(&self.out).unwrap_or_default();
+        let mut accounts = Vec::new();
+        for i in 0..self.node_count {
+            match self.existing_account(i, &saved) {
+                Some(account) => accounts.push(account),
+                None => println!("{:?}", self.account_command(i)),
+            }
+        }
+        let genesis = self.read_genesis();
+        let genesis_ok = accounts.len() == self.node_count
+            && genesis.as_ref().is_some_and(|g| self.genesis_diff(g, &accounts).is_none());
+        if !genesis_ok {
+            println!("{:?}", self.puppeth_command());
+        }
+        let recorded = genesis.filter(|_| genesis_ok);
+        for i in 0..self.node_count {
+            if recorded.is_none() || self.node_genesis(i) != recorded {
+                println!("{:?}", self.init_command(i, &self.genesis_path()));
+            }
+        }
+    }
+
+    fn genesis_path(&self) -> String {
         let mut genesis_dir = self.nodes_dir.clone();
         genesis_dir.push(Path::new(&format!("{}.json", NETWORK)));
-        let genesis_dir = genesis_dir.into_os_string().into_string().unwrap();
+        genesis_dir.into_os_string().into_string().unwrap()
+    }
 
-        for i in 0..self.node_count {
-            self.init_node(i, &genesis_dir);
+    fn read_genesis(&self) -> Option<serde_json::Value> {
+        let contents = fs::read_to_string(self.genesis_path()).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    // what sets the existing genesis apart from the one requested, None if nothing
+    fn genesis_diff(&self, genesis: &serde_json::Value, accounts: &[Address]) -> Option<String> {
+        let chain_id = genesis["config"]["chainId"].as_u64();
+        if chain_id != Some(NETWORK_ID) {
+            return Some(format!("chain id {:?} instead of {}", chain_id, NETWORK_ID));
+        }
+        let period = genesis["config"]["clique"]["period"].as_u64();
+        if let Some(p) = self.block_period {
+            if period != Some(p) {
+                return Some(format!("block period {:?} instead of {}", period, p));
+            }
+        }
+        // 32 bytes of vanity, then the 20 byte signers, then room for a seal
+        let extra = genesis["extraData"].as_str().unwrap_or_default().trim_start_matches("0x").to_lowercase();
+        let signers = extra.get(64..extra.len().saturating_sub(130)).unwrap_or_default();
+        // puppeth writes the signers sorted by address
+        let mut signers: Vec<&str> = (0..signers.len() / 40).map(|i| &signers[i*40..(i+1)*40]).collect();
+        signers.sort();
+        let mut sealers: Vec<String> = accounts.iter().take(self.sealer_count).map(|a| a.to_lowercase()).collect();
+        sealers.sort();
+        if signers != sealers {
+            return Some(format!("{} signer(s) instead of the first {} account(s)", signers.len(), self.sealer_count));
+        }
+        let alloc = genesis["alloc"].as_object().cloned().unwrap_or_default();
+        let alloc: Vec<String> = alloc.keys().map(|a| a.trim_start_matches("0x").to_lowercase()).collect();
+        if let Some(missing) = accounts.iter().find(|a| !alloc.contains(&a.to_lowercase())) {
+            return Some(format!("account {} is not funded", missing));
         }
+        None
     }
 
-    fn init_node(&self, id: usize, genesis_dir: &str) {
-        let mut geth = Command::new(&self.geth_dir)
-            .arg(format!("--datadir={}", node_dir(&self.nodes_dir, id)))
+    // a copy of the genesis a node was initialized with, kept beside its chain
+    // data so that it goes away with it
+    fn node_genesis_path(&self, id: usize) -> PathBuf {
+        Path::new(&node_dir(&self.nodes_dir, id)).join("geth").join("genesis.json")
+    }
+
+    fn node_genesis(&self, id: usize) -> Option<serde_json::Value> {
+        let contents = fs::read_to_string(self.node_genesis_path(id)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    // geth refuses to init chain data over another genesis
+    fn reset_chain(&self, id: usize) {
+        let geth = Path::new(&node_dir(&self.nodes_dir, id)).join("geth");
+        for dir in ["chaindata", "lightchaindata"] {
+            if geth.join(dir).exists() {
+                fs::remove_dir_all(geth.join(dir)).unwrap();
+            }
+        }
+    }
+
+    fn init_nodes(&self) -> bool {
+        let genesis = self.read_genesis().expect("Genesis was not written");
+        (0..self.node_count).all(|i| self.init_one(i, &genesis))
+    }
+
+    fn init_one(&self, id: usize, genesis: &serde_json::Value) -> bool {
+        let genesis_dir = self.genesis_path();
+        if self.node_genesis(id).as_ref() == Some(genesis) {
+            println!("Node {} is initialized", id);
+            return true;
+        }
+        let mut done = self.init_node(id, &genesis_dir);
+        if !done && self.force {
+            println!("Resetting the chain data of node {}", id);
+            self.reset_chain(id);
+            done = self.init_node(id, &genesis_dir);
+        }
+        if !done {
+            println!("Init of node {} failed, its chain data may belong to another genesis", id);
+            println!("Use --force to reset it, or `clean --chain --node {}`", id);
+            return false;
+        }
+        fs::copy(&genesis_dir, self.node_genesis_path(id)).unwrap();
+        true
+    }
+
+    // an account and a datadir initialized with the existing genesis for a node
+    // joining a running network, its address is appended to the saved accounts
+    pub fn init_joining_node(&self, id: usize) -> Option<Address> {
+        let genesis = self.read_genesis()?;
+        let mut saved = utils::load_addrs(&self.out).unwrap_or_default();
+        assert_eq!(saved.len(), id, "Node {} does not follow the saved accounts", id);
+        let account = self.existing_account(id, &saved).unwrap_or_else(|| self.create_account(id));
+        saved.push(account.clone());
+        utils::save_addrs(saved, &self.out).unwrap();
+        self.write_password();
+        if !self.init_one(id, &genesis) {
+            return None;
+        }
+        Some(account)
+    }
+
+    // the accounts are created with an empty password, which the nodes unlock with
+    fn write_password(&self) {
+        fs::create_dir_all(&self.nodes_dir).unwrap();
+        fs::write(utils::password_file(&self.nodes_dir), "").expect("Write password file failed");
+    }
+
+    fn init_command(&self, id: usize, genesis_dir: &str) -> Command {
+        let mut geth = Command::new(&self.geth_dir);
+        geth.arg(format!("--datadir={}", node_dir(&self.nodes_dir, id)))
             .arg("init")
-            .arg(genesis_dir)
-            .spawn()
-            .unwrap();
-        geth.wait().unwrap();
+            .arg(genesis_dir);
+        geth
+    }
+
+    fn init_node(&self, id: usize, genesis_dir: &str) -> bool {
+        let mut geth = self.init_command(id, genesis_dir).spawn().unwrap();
+        geth.wait().unwrap().success()
+    }
+
+    fn puppeth_command(&self) -> Command {
+        let mut puppeth = Command::new(&self.puppeth_dir);
+        if let Some(ref home) = self.puppeth_home {
+            puppeth.env("HOME", home);
+        }
+        puppeth
+    }
+
+    // where puppeth keeps the configuration of the network between runs
+    pub fn puppeth_network(&self) -> PathBuf {
+        let mut path = self.puppeth_home.clone().unwrap_or_else(|| env::current_dir().unwrap());
+        path.push(Path::new(".puppeth"));
+        path.push(Path::new(NETWORK));
+        path
     }
 
     // assumes self.node_count >= self.sealer_count
-    fn create_genesis(&self, accounts: &Vec<Address>) {
-        let mut dir = env::current_dir().unwrap();
-        dir.push(Path::new(".puppeth"));
-        let exist = dir.is_dir();
+    fn create_genesis(&self, accounts: &[Address]) {
+        let exist = self.puppeth_network().exists();
 
-        let mut puppeth = Command::new(&self.puppeth_dir)
+        let mut puppeth = self.puppeth_command()
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
@@ -101,15 +263,17 @@ impl NodeInitializer {
         itr.send_on_prompt(b"2");
         itr.send_on_prompt(b"1");
         itr.send_on_prompt(b"2");
-        itr.send_on_prompt(b"");
+        // clique block period, puppeth defaults to 15 seconds
+        let period = self.block_period.map_or(String::new(), |p| p.to_string());
+        itr.send_on_prompt(period.as_bytes());
 
-        for i in 0..self.sealer_count {
-            itr.send_on_prompt(accounts[i].as_bytes());
+        for account in accounts.iter().take(self.sealer_count) {
+            itr.send_on_prompt(account.as_bytes());
         }
         itr.send_on_prompt(b"");
 
-        for i in 0..self.node_count {
-            itr.send_on_prompt(accounts[i].as_bytes());
+        for account in accounts.iter().take(self.node_count) {
+            itr.send_on_prompt(account.as_bytes());
         }
         itr.send_on_prompt(b"");
 
@@ -127,22 +291,62 @@ impl NodeInitializer {
         puppeth.kill().unwrap();
     }
 
+    // addresses of the keys in the keystore of a node
+    fn keystore_accounts(&self, id: usize) -> Vec<Address> {
+        let keystore = Path::new(&node_dir(&self.nodes_dir, id)).join("keystore");
+        let mut files: Vec<PathBuf> = match fs::read_dir(keystore) {
+            Ok(entries) => entries.map(|e| e.unwrap().path()).collect(),
+            Err(_) => return vec![],
+        };
+        // key files are named after their creation time
+        files.sort();
+        files.iter()
+            .filter_map(|f| fs::read_to_string(f).ok())
+            .filter_map(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+            .filter_map(|k| k["address"].as_str().map(String::from))
+            .collect()
+    }
+
+    // the account saved for the node if its key is still there, otherwise the
+    // oldest key of its keystore
+    fn existing_account(&self, id: usize, saved: &[Address]) -> Option<Address> {
+        let keys = self.keystore_accounts(id);
+        saved.get(id)
+            .and_then(|a| keys.iter().find(|k| k.eq_ignore_ascii_case(a)).map(|_| a.clone()))
+            .or_else(|| keys.into_iter().next())
+    }
+
+    // saved after every new account so that an interrupted init resumes
     fn create_accounts(&self) -> Vec<Address> {
+        let saved = utils::load_addrs(&self.out).unwrap_or_default();
         let mut accounts = vec![];
         for i in 0..self.node_count {
-            let account = self.create_account(i);
+            let account = match self.existing_account(i, &saved) {
+                Some(account) => {
+                    println!("Node {} has account {}", i, account);
+                    account
+                },
+                None => self.create_account(i),
+            };
             accounts.push(account);
+            // accounts of nodes added by `add-node` follow node.count and are kept
+            let kept = saved.iter().skip(accounts.len()).cloned();
+            utils::save_addrs(accounts.iter().cloned().chain(kept).collect(), &self.out).unwrap();
         }
-        utils::save_addrs(accounts.clone(), &self.out).unwrap();
 
         accounts
     }
 
-    fn create_account(&self, id: usize) -> Address {
-        let mut geth = Command::new(&self.geth_dir)
-            .arg(format!("--datadir={}", node_dir(&self.nodes_dir, id)))
+    fn account_command(&self, id: usize) -> Command {
+        let mut geth = Command::new(&self.geth_dir);
+        geth.arg(format!("--datadir={}", node_dir(&self.nodes_dir, id)))
             .arg("account")
-            .arg("new")
+            .arg("new");
+        geth
+    }
+
+    fn create_account(&self, id: usize) -> Address {
+        let mut geth = self.account_command(id)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
@@ -152,7 +356,101 @@ impl NodeInitializer {
         geth_in.write_all(b"\n\n").unwrap();
         let mut res = String::new();
         geth_out.read_to_string(&mut res).unwrap();
+        geth.wait().unwrap();
         let idx = res.find("0x").unwrap() + 2;
         res[idx..(idx+40)].to_string()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn initializer(nodes_dir: &Path) -> NodeInitializer {
+        let cfg: Value = toml::from_str(&format!(r#"
+            [bin]
+            geth_dir = "geth"
+            puppeth_dir = "puppeth"
+            [node]
+            dir = "{nodes}"
+            count = 3
+            sealer_count = 2
+            [init]
+            accounts_dir = "{nodes}/accounts.toml"
+            block_period = 5
+        "#, nodes = nodes_dir.display())).unwrap();
+        NodeInitializer::new_with_cfg(&cfg)
+    }
+
+    #[test]
+    fn test_genesis_diff() {
+        let ni = initializer(Path::new("nodes"));
+        let accounts: Vec<Address> = vec![format!("{:040}", 1), format!("{:040}", 2), format!("{:040}", 3)];
+        let genesis = serde_json::json!({
+            "config": {"chainId": NETWORK_ID, "clique": {"period": 5, "epoch": 30000}},
+            "extraData": format!("0x{}{}{}{}", "0".repeat(64), accounts[0], accounts[1], "0".repeat(130)),
+            "alloc": {
+                accounts[0].clone(): {"balance": "0x1"},
+                accounts[1].clone(): {"balance": "0x1"},
+                accounts[2].clone(): {"balance": "0x1"},
+            },
+        });
+        assert_eq!(ni.genesis_diff(&genesis, &accounts), None);
+
+        let mut other = accounts.clone();
+        other[2] = format!("{:040}", 4);
+        assert!(ni.genesis_diff(&genesis, &other).unwrap().contains("not funded"));
+        other[1] = format!("{:040}", 5);
+        assert!(ni.genesis_diff(&genesis, &other).unwrap().contains("signer"));
+
+        // in the order puppeth writes them, sorted by address
+        let unsorted: Vec<Address> = vec![format!("{:040}", 9), format!("{:040}", 8), format!("{:040}", 3)];
+        let mut sorted = genesis.clone();
+        sorted["extraData"] = serde_json::json!(format!("0x{}{}{}{}", "0".repeat(64), unsorted[1], unsorted[0], "0".repeat(130)));
+        sorted["alloc"] = serde_json::json!({
+            unsorted[0].clone(): {"balance": "0x1"},
+            unsorted[1].clone(): {"balance": "0x1"},
+            unsorted[2].clone(): {"balance": "0x1"},
+        });
+        assert_eq!(ni.genesis_diff(&sorted, &unsorted), None);
+
+        let mut slow = genesis.clone();
+        slow["config"]["clique"]["period"] = serde_json::json!(15);
+        assert!(ni.genesis_diff(&slow, &accounts).unwrap().contains("block period"));
+    }
+
+    #[test]
+    fn test_existing_account() {
+        let dir = std::env::temp_dir().join(format!("ethrunner-init-{}", std::process::id()));
+        let ni = initializer(&dir);
+        let keystore = Path::new(&node_dir(&dir, 0)).join("keystore");
+        fs::create_dir_all(&keystore).unwrap();
+        fs::write(keystore.join("UTC--2021-01-01--aa"), r#"{"address":"aa"}"#).unwrap();
+        fs::write(keystore.join("UTC--2021-01-02--bb"), r#"{"address":"bb"}"#).unwrap();
+
+        // the saved account wins if its key is there, the oldest key otherwise
+        assert_eq!(ni.existing_account(0, &[String::from("BB")]), Some(String::from("BB")));
+        assert_eq!(ni.existing_account(0, &[String::from("cc")]), Some(String::from("aa")));
+        assert_eq!(ni.existing_account(0, &[]), Some(String::from("aa")));
+        assert_eq!(ni.existing_account(1, &[]), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_accounts_keeps_added() {
+        let dir = std::env::temp_dir().join(format!("ethrunner-init-added-{}", std::process::id()));
+        let ni = initializer(&dir);
+        let saved: Vec<Address> = (0..4).map(|i| format!("{:040}", i)).collect();
+        for (id, address) in saved.iter().enumerate().take(3) {
+            let keystore = Path::new(&node_dir(&dir, id)).join("keystore");
+            fs::create_dir_all(&keystore).unwrap();
+            fs::write(keystore.join(format!("UTC--2021-01-01--{}", id)), format!(r#"{{"address":"{}"}}"#, address)).unwrap();
+        }
+        utils::save_addrs(saved.clone(), &dir.join("accounts.toml")).unwrap();
+
+        // node 3 was added to a network of three, it stays in accounts.toml
+        assert_eq!(ni.create_accounts(), saved[..3].to_vec());
+        assert_eq!(utils::load_addrs(&dir.join("accounts.toml")).unwrap(), saved);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+// the library API is the network and its test fixture; the modules marked
+// doc(hidden) are the plumbing of the ethereum_runner binary and may change
+#[doc(hidden)]
+pub mod init;
+mod utils;
+#[doc(hidden)]
+pub mod run;
+mod analyze;
+mod results;
+#[doc(hidden)]
+pub mod compare;
+#[doc(hidden)]
+pub mod sweep;
+mod scenario;
+mod partition;
+mod relay;
+mod churn;
+mod sealing;
+#[doc(hidden)]
+pub mod signers;
+mod verify;
+mod wait;
+mod txpool;
+mod propagation;
+mod metrics;
+mod dashboard;
+mod state;
+#[doc(hidden)]
+pub mod attach;
+mod network;
+mod fixture;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod control;
+#[doc(hidden)]
+pub mod snapshot;
+#[doc(hidden)]
+pub mod membership;
+
+pub use network::{Network, NetworkBuilder, NodeHandle, Topology};
+pub use fixture::TestNetwork;
+
+pub const NETWORK: &str = "auto_test";
+pub const NETWORK_ID: u64 = 666;
+
+pub type Address = String;
@@ -1,51 +1,435 @@
-mod init;
-mod utils;
-mod run;
+use std::io;
 use std::path::PathBuf;
-use clap::{Parser, ArgGroup};
-use std::process::{Command, Stdio};
-use std::io::{self, Read, BufRead, Write};
-use std::str::FromStr;
+use std::process;
+use std::time::Duration;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use toml::Value;
 
-use utils::*;
-
-const NETWORK: &str = "auto_test";
-const NETWORK_ID: u64 = 666;
-
-type Address = String;
+use ethereum_runner::{attach, compare, config, control, init, membership, run, signers, snapshot, sweep};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
-#[clap(group(
-    ArgGroup::new("mode")
-        .required(true)
-        .args(&["init", "run"])
-))]
 struct Cli {
-    /// Initialize nodes
-    #[clap(long)]
-    init: bool,
+    /// Path of configuration file
+    #[clap(long, global = true, parse(from_os_str), value_name = "FILE", default_value = "config.toml")]
+    config: PathBuf,
 
-    /// Start the consensus network
-    #[clap(long)]
-    run: bool,
+    #[clap(flatten)]
+    overrides: Overrides,
 
-    /// Path of configuration file
-    #[clap(long, parse(from_os_str), value_name = "FILE")]
-    config: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Commands,
 }
 
-fn main() {
-    let mut cli = Cli::parse();
-    if let None = cli.config {
-        cli.config = Some(PathBuf::from_str("config.toml").unwrap());
+// values of the config file replaced from the command line
+#[derive(Args)]
+struct Overrides {
+    /// Number of nodes, node.count in the config
+    #[clap(long, global = true)]
+    nodes: Option<usize>,
+
+    /// Number of sealers, node.sealer_count in the config
+    #[clap(long, global = true)]
+    sealers: Option<usize>,
+
+    /// Clique block period in seconds, init.block_period in the config
+    #[clap(long, global = true)]
+    block_period: Option<u64>,
+
+    /// Seed of the random topology, node.seed in the config
+    #[clap(long, global = true)]
+    seed: Option<u64>,
+
+    /// Any other value of the config with a dotted key, e.g. --set test.rate=2.0
+    #[clap(long = "set", global = true, value_name = "KEY=VALUE", value_parser = config::parse_override)]
+    set: Vec<(String, Value)>,
+}
+
+impl Overrides {
+    fn cell(&self) -> sweep::Cell {
+        let mut cell = Vec::new();
+        if let Some(n) = self.nodes {
+            cell.push((String::from("node.count"), Value::from(n as i64)));
+        }
+        if let Some(n) = self.sealers {
+            cell.push((String::from("node.sealer_count"), Value::from(n as i64)));
+        }
+        if let Some(p) = self.block_period {
+            cell.push((String::from("init.block_period"), Value::from(p as i64)));
+        }
+        if let Some(s) = self.seed {
+            cell.push((String::from("node.seed"), Value::from(s as i64)));
+        }
+        cell.extend(self.set.iter().cloned());
+        cell
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Create the accounts, the genesis and the datadirs of the nodes
+    Init {
+        /// Replace an existing genesis that differs from the requested one, resetting the chain data
+        #[clap(long)]
+        force: bool,
+
+        /// Print the commands instead of running them
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Start the network, then run the test or scenario of the config if any
+    Run {
+        /// Directory where the results of each test run are written
+        #[clap(long, parse(from_os_str), value_name = "DIR", default_value = "results")]
+        results_dir: PathBuf,
+
+        /// Scenario file executed instead of the plain test once the nodes are connected
+        #[clap(long, parse(from_os_str), value_name = "FILE")]
+        scenario: Option<PathBuf>,
+
+        /// Write the geth logs to geth.log in each datadir instead of the terminal
+        #[clap(long)]
+        node_logs: bool,
+
+        /// Print the geth commands and peerings instead of starting the network
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Start the network and run the plain transaction test, whatever the config says
+    Bench {
+        /// Rounds of one transaction per node, test.n in the config
+        #[clap(long)]
+        txs: Option<usize>,
+
+        /// Seconds the transactions are sent for, test.period in the config
+        #[clap(long)]
+        period: Option<u64>,
+
+        /// Rounds sent per second, as fast as possible if omitted
+        #[clap(long)]
+        rate: Option<f64>,
+
+        /// Directory where the results of each test run are written
+        #[clap(long, parse(from_os_str), value_name = "DIR", default_value = "results")]
+        results_dir: PathBuf,
+
+        /// Write the geth logs to geth.log in each datadir instead of the terminal
+        #[clap(long)]
+        node_logs: bool,
+
+        /// Print the geth commands and peerings instead of starting the network
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Stop the runner and the nodes of a running network
+    Stop {
+        /// Seconds the nodes are given to exit before they are killed
+        #[clap(long, default_value = "10")]
+        timeout: u64,
+    },
+    /// Show the health of the nodes of a running network, exits with 1 if any is unhealthy
+    Status {
+        /// Print the status as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Remove the nodes directory with all accounts and chain data, or parts of it
+    Clean {
+        /// Reset the chain data only, keeping keystores, nodekeys and accounts
+        #[clap(long)]
+        chain: bool,
+
+        /// Reset only these nodes, may be repeated
+        #[clap(long = "node", value_name = "ID")]
+        nodes: Vec<usize>,
+
+        /// Do not ask for confirmation
+        #[clap(long, short)]
+        yes: bool,
+    },
+    /// Print the peerings the nodes dial
+    Topology {
+        /// Print a graphviz digraph, sealers are boxes
+        #[clap(long)]
+        dot: bool,
+    },
+    /// Print the geth log of a node, written with `run --node-logs` or by the dashboard
+    Logs {
+        #[clap(long)]
+        node: usize,
+
+        /// Number of lines from the end
+        #[clap(long, short = 'n', default_value = "50")]
+        lines: usize,
+
+        /// Keep printing what the node logs
+        #[clap(long, short)]
+        follow: bool,
+    },
+    /// Open an interactive console on a node of a running network
+    Attach {
+        #[clap(long)]
+        node: usize,
+    },
+    /// Evaluate an expression on nodes of a running network and print the results
+    Exec {
+        #[clap(long, required_unless_present = "all", conflicts_with = "all")]
+        node: Option<usize>,
+
+        /// Run on every node
+        #[clap(long)]
+        all: bool,
+
+        /// Javascript evaluated by the geth console, e.g. eth.blockNumber
+        expr: String,
+    },
+    /// Check that the nodes of a running network agree on the chain, exits with 1 if not
+    Verify {
+        /// Seconds the nodes are given to agree on a head
+        #[clap(long, default_value = "60")]
+        timeout: u64,
+
+        /// Heights whose hashes are compared across the nodes
+        #[clap(long, default_value = "10")]
+        samples: usize,
+    },
+    /// Start the network and watch it in a full-screen terminal dashboard
+    Dashboard,
+    /// List or vote on the clique signers of a running network
+    Sealers {
+        #[clap(subcommand)]
+        op: SealersOp,
+    },
+    /// Create, start and peer one more node on a running network
+    AddNode {
+        /// Number of running nodes it dials, node.peer_count in the config
+        #[clap(long)]
+        peers: Option<usize>,
+
+        /// Have the signers vote it in once it runs
+        #[clap(long)]
+        sealer: bool,
+
+        /// Seconds to wait for the vote to pass
+        #[clap(long, default_value = "120")]
+        timeout: u64,
+    },
+    /// Detach and stop a node of a running network, voting it out first if it seals
+    RemoveNode {
+        node: usize,
+
+        /// Seconds to wait for the vote to pass
+        #[clap(long, default_value = "120")]
+        timeout: u64,
+    },
+    /// Save the state of the whole network to an archive or recreate it from one
+    Snapshot {
+        #[clap(subcommand)]
+        op: SnapshotOp,
+    },
+    /// Compare results against the first directory, exits with 1 on a significant regression
+    Compare {
+        /// Result directories, each holding one run or a set of repeated runs
+        #[clap(parse(from_os_str), min_values = 2, required = true)]
+        dirs: Vec<PathBuf>,
+
+        /// Print the tables as Markdown
+        #[clap(long)]
+        markdown: bool,
+    },
+    /// Run every combination of parameters declared in an experiment file
+    Sweep {
+        /// Path of experiment file
+        #[clap(parse(from_os_str), value_name = "FILE")]
+        experiment: PathBuf,
+    },
+    /// Print the completion script for a shell
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand)]
+enum SealersOp {
+    /// Print the current signers
+    List,
+    /// Have the signers vote a node in and start its miner
+    Add {
+        node: usize,
+
+        /// Seconds to wait for the vote to pass
+        #[clap(long, default_value = "120")]
+        timeout: u64,
+    },
+    /// Have the signers vote a node out and stop its miner
+    Remove {
+        node: usize,
+
+        /// Seconds to wait for the vote to pass
+        #[clap(long, default_value = "120")]
+        timeout: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotOp {
+    /// Archive the datadirs, accounts, genesis and topology as snapshot.dir/<NAME>.tar.gz
+    Save {
+        name: String,
+
+        /// Stop a running network first instead of refusing
+        #[clap(long)]
+        stop: bool,
+    },
+    /// Recreate the nodes directory from a snapshot after verifying its checksums
+    Restore {
+        name: String,
+
+        /// Do not ask before replacing the nodes directory
+        #[clap(long, short)]
+        yes: bool,
+    },
+    /// Print the saved snapshots
+    List,
+}
+
+fn exit_unless(ok: bool) {
+    if !ok {
+        process::exit(1);
     }
-    if cli.init {
-        let ni = init::NodeInitializer::new_with_cfg_file(cli.config.unwrap().as_path());
-        ni.do_init_node();
-    } else if cli.run {
-        let nr = run::NodeRunner::new_with_cfg_file(cli.config.as_ref().unwrap().as_path());
-        nr.do_run_nodes();
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let mut overrides = cli.overrides.cell();
+    let cfg_path = cli.config;
+    let load = |overrides: &sweep::Cell| config::load(&cfg_path, overrides);
+    match cli.command {
+        Commands::Init { force, dry_run } => {
+            let mut ni = init::NodeInitializer::new_with_cfg(&load(&overrides));
+            ni.set_force(force);
+            if dry_run {
+                ni.print_commands();
+            } else {
+                exit_unless(ni.do_init_node());
+            }
+        },
+        Commands::Run { results_dir, scenario, node_logs, dry_run } => {
+            if node_logs {
+                overrides.push((String::from("run.node_logs"), Value::from(true)));
+            }
+            let mut nr = run::NodeRunner::new_with_cfg(&load(&overrides));
+            if dry_run {
+                nr.print_commands();
+                return;
+            }
+            nr.set_results_dir(results_dir);
+            if let Some(scenario) = scenario {
+                nr.set_scenario(scenario);
+            }
+            nr.do_run_nodes();
+        },
+        Commands::Bench { txs, period, rate, results_dir, node_logs, dry_run } => {
+            overrides.push((String::from("test.test"), Value::from(true)));
+            if let Some(n) = txs {
+                overrides.push((String::from("test.n"), Value::from(n as i64)));
+            }
+            if let Some(p) = period {
+                overrides.push((String::from("test.period"), Value::from(p as i64)));
+            }
+            if let Some(r) = rate {
+                overrides.push((String::from("test.rate"), Value::from(r)));
+            }
+            if node_logs {
+                overrides.push((String::from("run.node_logs"), Value::from(true)));
+            }
+            let mut cfg = load(&overrides);
+            // the plain test, not the scenario the config may point to
+            cfg["run"].as_table_mut().unwrap().remove("scenario");
+            let mut nr = run::NodeRunner::new_with_cfg(&cfg);
+            if dry_run {
+                nr.print_commands();
+                return;
+            }
+            nr.set_results_dir(results_dir);
+            nr.do_run_nodes();
+        },
+        Commands::Stop { timeout } => {
+            exit_unless(control::stop(&load(&overrides), Duration::from_secs(timeout)));
+        },
+        Commands::Status { json } => {
+            exit_unless(control::status(&load(&overrides), json));
+        },
+        Commands::Clean { chain, nodes, yes } => {
+            exit_unless(control::clean(&load(&overrides), &nodes, chain, yes));
+        },
+        Commands::Topology { dot } => {
+            control::topology(&load(&overrides), dot);
+        },
+        Commands::Logs { node, lines, follow } => {
+            exit_unless(control::logs(&load(&overrides), node, lines, follow));
+        },
+        Commands::Attach { node } => {
+            attach::NodeConsole::new_with_cfg(&load(&overrides)).attach(node);
+        },
+        Commands::Exec { node, all: _, expr } => {
+            exit_unless(attach::NodeConsole::new_with_cfg(&load(&overrides)).exec(node, &expr));
+        },
+        Commands::Verify { timeout, samples } => {
+            exit_unless(control::verify(&load(&overrides), Duration::from_secs(timeout), samples));
+        },
+        Commands::Dashboard => {
+            run::NodeRunner::new_with_cfg(&load(&overrides)).do_dashboard();
+        },
+        Commands::Sealers { op } => {
+            let sc = signers::SealerControl::new_with_cfg(&load(&overrides));
+            let passed = match op {
+                SealersOp::List => {
+                    sc.list();
+                    true
+                },
+                SealersOp::Add { node, timeout } => sc.propose(node, true, Duration::from_secs(timeout)),
+                SealersOp::Remove { node, timeout } => sc.propose(node, false, Duration::from_secs(timeout)),
+            };
+            exit_unless(passed);
+        },
+        Commands::AddNode { peers, sealer, timeout } => {
+            let m = membership::Membership::new_with_cfg(&load(&overrides));
+            exit_unless(m.add_node(peers, sealer, Duration::from_secs(timeout)));
+        },
+        Commands::RemoveNode { node, timeout } => {
+            let m = membership::Membership::new_with_cfg(&load(&overrides));
+            exit_unless(m.remove_node(node, Duration::from_secs(timeout)));
+        },
+        Commands::Snapshot { op } => {
+            let snapshots = snapshot::Snapshots::new_with_cfg(&load(&overrides));
+            let done = match op {
+                SnapshotOp::Save { name, stop } => snapshots.save(&name, stop).unwrap(),
+                SnapshotOp::Restore { name, yes } => snapshots.restore(&name, yes).unwrap(),
+                SnapshotOp::List => {
+                    snapshots.list().unwrap();
+                    true
+                },
+            };
+            exit_unless(done);
+        },
+        Commands::Compare { dirs, markdown } => {
+            match compare::do_compare(&dirs, markdown) {
+                Ok(regressed) => exit_unless(!regressed),
+                Err(e) => {
+                    println!("Compare failed: {}", e);
+                    process::exit(1);
+                },
+            }
+        },
+        Commands::Sweep { experiment } => {
+            sweep::Experiment::new_with_file(&experiment).do_sweep().unwrap();
+        },
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "ethereum_runner", &mut io::stdout());
+        },
     }
     // let mut remote = Command::new("ssh")
     //     .arg("-T")
@@ -60,4 +444,4 @@ fn main() {
     // itr.send(String::from("cd ~/桌面/SGX/opensgx2/user").as_bytes()).unwrap();
     // itr.send(b"../opensgx test/core/txpool").unwrap();
     // remote.wait().unwrap();
-}
\ No newline at end of file
+}
@@ -0,0 +1,276 @@
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use toml::Value;
+
+use crate::attach::rpc_request;
+use crate::init::NodeInitializer;
+use crate::run::{NodeRunner, READY_TIMEOUT};
+use crate::signers::SealerControl;
+use crate::state::{ManagedNode, RunState};
+use crate::sweep;
+use crate::utils;
+use crate::wait::{wait_until, POLL_INTERVAL};
+
+// seconds a removed node is given to shut down before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// `add-node` and `remove-node`, grow and shrink a network run by another process
+pub struct Membership {
+    cfg:        Value,
+    nodes_dir:  PathBuf,
+    accounts:   PathBuf,
+    node_count: usize,
+}
+
+// peers a node joining as `id` dials: its entry of an explicit topology, or
+// `k` of the candidates sampled like the random topology
+pub fn pick_peers(cfg: &Value, id: usize, candidates: &[usize], k: usize) -> Vec<usize> {
+    let random = cfg["node"].get("random_connect").is_some_and(|v| v.as_bool().unwrap());
+    if !random {
+        if let Some(peers) = cfg["node"].get("connection").and_then(|c| c.as_array()).and_then(|c| c.get(id)) {
+            return peers.as_array().unwrap().iter()
+                .map(|p| p.as_integer().unwrap() as usize)
+                .filter(|p| candidates.contains(p))
+                .collect();
+        }
+    }
+    let k = k.min(candidates.len());
+    // seeded networks stay reproducible, each joining node draws its own peers
+    let seed = cfg["node"].get("seed").map_or_else(rand::random, |v| v.as_integer().unwrap() as u64);
+    let mut rng = StdRng::seed_from_u64(seed ^ id as u64);
+    let n = candidates.len() as i32;
+    NodeRunner::sample_with(&mut rng, k as i32, n, n).into_iter()
+        .map(|i| candidates[i as usize])
+        .collect()
+}
+
+fn enode(node: &ManagedNode) -> Option<String> {
+    node.enode.clone().or_else(|| {
+        let info = rpc_request(&node.ipc, "admin_nodeInfo", serde_json::json!([])).ok()?;
+        info["enode"].as_str().map(String::from)
+    })
+}
+
+impl Membership {
+    pub fn new_with_cfg(parsed: &Value) -> Membership {
+        Membership {
+            cfg:        parsed.clone(),
+            nodes_dir:  PathBuf::from(parsed["node"]["dir"].as_str().unwrap()),
+            accounts:   PathBuf::from(parsed["run"]["accounts_dir"].as_str().unwrap()),
+            node_count: parsed["node"]["count"].as_integer().unwrap() as usize,
+        }
+    }
+
+    // drops the account of node `id` and those after it, so that a failed
+    // `add-node` leaves accounts.toml as it found it
+    fn forget_account(&self, id: usize) {
+        let mut addrs = utils::load_addrs(&self.accounts).unwrap_or_default();
+        addrs.truncate(id);
+        utils::save_addrs(addrs, &self.accounts).unwrap();
+    }
+
+    fn load_state(&self) -> Option<RunState> {
+        match RunState::load(&self.nodes_dir) {
+            Ok(state) => Some(state),
+            Err(_) => {
+                println!("No run state in {}, start the network with `run` first", self.nodes_dir.display());
+                None
+            },
+        }
+    }
+
+    // creates, inits and starts a node detached from the runner, peers it with
+    // running nodes and, with `sealer`, has the signers vote it in
+    pub fn add_node(&self, peers: Option<usize>, sealer: bool, timeout: Duration) -> bool {
+        let state = match self.load_state() {
+            Some(state) => state,
+            None => return false,
+        };
+        let running: Vec<&ManagedNode> = state.nodes.iter()
+            .filter(|n| n.pid.is_some_and(utils::pid_alive))
+            .collect();
+        if running.is_empty() {
+            println!("No node of the network is running");
+            return false;
+        }
+        let id = state.nodes.len();
+        let saved = utils::load_addrs(&self.accounts).unwrap_or_default().len();
+        if saved != id {
+            println!("{} holds {} account(s) for {} node(s), run `init` first", self.accounts.display(), saved, id);
+            return false;
+        }
+        let address = match NodeInitializer::new_with_cfg(&self.cfg).init_joining_node(id) {
+            Some(address) => address,
+            None => {
+                self.forget_account(id);
+                return false;
+            },
+        };
+        println!("Node {} has account {}", id, address);
+
+        // a runner of its own, only to build the command line the others got; the
+        // runner of the network leaves nodes beyond node.count alone
+        let cfg = sweep::apply(&self.cfg, &vec![
+            (String::from("node.count"), Value::from(id as i64 + 1)),
+            (String::from("metrics.enabled"), Value::from(false)),
+            (String::from("run.tee"), Value::from(false)),
+        ]);
+        let nr = NodeRunner::new_with_cfg(&cfg);
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(NodeRunner::log_path(&self.nodes_dir, id))
+            .unwrap();
+        // left running when this process exits, `stop` and `remove-node` end it
+        #[allow(clippy::zombie_processes)]
+        let mut geth = nr.geth_command(id, false)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone().unwrap())
+            .stderr(log)
+            .spawn()
+            .unwrap();
+        let ipc = NodeRunner::ipc_endpoint(&self.nodes_dir, id);
+        let ready = wait_until(READY_TIMEOUT, POLL_INTERVAL, || {
+            rpc_request(&ipc, "eth_blockNumber", serde_json::json!([])).is_ok()
+        });
+        if !ready {
+            println!("Node {} did not become ready within {}s, see {}", id, READY_TIMEOUT.as_secs(), NodeRunner::log_path(&self.nodes_dir, id).display());
+            geth.kill().unwrap();
+            geth.wait().unwrap();
+            self.forget_account(id);
+            return false;
+        }
+        let node = ManagedNode {
+            id,
+            running:    true,
+            pid:        Some(geth.id()),
+            sealer:     false,
+            mining:     false,
+            ipc:        ipc.clone(),
+            enode:      None,
+            detached:   true,
+        };
+        let node = ManagedNode { enode: enode(&node), ..node };
+
+        let candidates: Vec<usize> = running.iter().map(|n| n.id).collect();
+        let k = peers.unwrap_or_else(|| self.cfg["node"].get("peer_count").map_or(2, |v| v.as_integer().unwrap() as usize));
+        for peer in pick_peers(&self.cfg, id, &candidates, k) {
+            match enode(state.node(peer)) {
+                Some(e) => {
+                    rpc_request(&ipc, "admin_addPeer", serde_json::json!([e])).unwrap();
+                    println!("Node {} dials node {}", id, peer);
+                },
+                None => println!("Node {} has no enode, not dialing it", peer),
+            }
+        }
+
+        // the runner may have rewritten the state meanwhile
+        RunState::update(&self.nodes_dir, |state| state.nodes.push(node)).expect("Write run state failed");
+        println!("Started node {} with pid {}", id, geth.id());
+
+        if sealer {
+            let voted = SealerControl::new_with_cfg(&self.cfg).propose(id, true, timeout);
+            RunState::update(&self.nodes_dir, |state| {
+                if let Some(n) = state.nodes.get_mut(id) {
+                    n.sealer = voted;
+                    n.mining = voted;
+                }
+            }).expect("Write run state failed");
+            if !voted {
+                println!("Node {} runs, but the signers did not vote it in within {}s", id, timeout.as_secs());
+                return false;
+            }
+            println!("Node {} is a signer", id);
+        }
+        true
+    }
+
+    // votes a signer out, even a dead one as the others still count on it, has
+    // the others drop their peerings with the node and stops it; the last node
+    // added by `add-node` also leaves the accounts and the run state, others stay
+    // listed as stopped so that node.count and the ids after them do not shift
+    pub fn remove_node(&self, id: usize, timeout: Duration) -> bool {
+        let state = match self.load_state() {
+            Some(state) => state,
+            None => return false,
+        };
+        let node = match state.nodes.get(id) {
+            Some(node) => node.clone(),
+            None => {
+                println!("Network has no node {}", id);
+                return false;
+            },
+        };
+        let alive = node.pid.is_some_and(utils::pid_alive);
+        if node.sealer {
+            println!("Voting node {} out of the signers", id);
+            if !SealerControl::new_with_cfg(&self.cfg).propose(id, false, timeout) {
+                println!("The signers did not vote node {} out within {}s, it keeps running", id, timeout.as_secs());
+                return false;
+            }
+        }
+        if let Some(e) = enode(&node).filter(|_| alive) {
+            for other in state.nodes.iter().filter(|n| n.id != id && n.pid.is_some_and(utils::pid_alive)) {
+                let _ = rpc_request(&other.ipc, "admin_removePeer", serde_json::json!([e]));
+            }
+        }
+        if let Some(pid) = node.pid.filter(|_| alive) {
+            if !node.detached {
+                println!("Node {} belongs to runner {}, which sees it exit", id, state.runner_pid);
+            }
+            utils::terminate(pid, false);
+            if !wait_until(STOP_TIMEOUT, POLL_INTERVAL, || !utils::pid_alive(pid)) {
+                utils::terminate(pid, true);
+            }
+        }
+
+        let mut last = false;
+        RunState::update(&self.nodes_dir, |state| {
+            if node.detached && id >= self.node_count && id + 1 == state.nodes.len() {
+                state.nodes.pop();
+                last = true;
+            } else {
+                let n = &mut state.nodes[id];
+                n.running = false;
+                n.pid = None;
+                n.sealer = false;
+                n.mining = false;
+            }
+        }).expect("Write run state failed");
+        if last {
+            self.forget_account(id);
+            println!("Removed node {}, its datadir stays in {}", id, Path::new(&utils::node_dir(&self.nodes_dir, id)).display());
+        } else {
+            println!("Stopped node {}, it stays listed as nodes after it keep their ids", id);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_peers() {
+        let random: Value = toml::from_str("[node]\nrandom_connect = true\nseed = 7").unwrap();
+        let candidates = vec![0, 2, 3, 5];
+        let peers = pick_peers(&random, 6, &candidates, 2);
+        assert_eq!(peers.len(), 2);
+        assert!(peers.iter().all(|p| candidates.contains(p)));
+        assert_ne!(peers[0], peers[1]);
+        // seeded, so the same node draws the same peers again
+        assert_eq!(pick_peers(&random, 6, &candidates, 2), peers);
+        assert_eq!(pick_peers(&random, 6, &candidates, 10).len(), 4);
+
+        // an explicit entry for the node wins, minus the peers that are down
+        let explicit: Value = toml::from_str("[node]\nrandom_connect = false\nconnection = [[1], [0], [0, 1, 3]]").unwrap();
+        assert_eq!(pick_peers(&explicit, 2, &[0, 1], 2), vec![0, 1]);
+        assert_eq!(pick_peers(&explicit, 3, &[0, 1], 1).len(), 1);
+    }
+}
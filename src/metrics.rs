@@ -0,0 +1,336 @@
+use std::io::{self, Read, Write};
+use std::fmt::Write as _;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use toml::Value;
+
+use crate::run::NodeRunner;
+use crate::utils::json_u64;
+
+// how often the runner refreshes what it polls from the nodes
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// how often the idle server checks whether the runner is gone
+const STOP_POLL: Duration = Duration::from_millis(100);
+// upper bounds in seconds, latencies come from block timestamps
+const LATENCY_BUCKETS: [f64; 10] = [1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0, 60.0, 120.0, 300.0];
+// recent blocks kept for the dashboard charts
+const BLOCK_HISTORY: usize = 120;
+
+#[derive(Debug, Default, Clone)]
+pub struct NodeMetrics {
+    pub sealer:     bool,
+    pub up:         bool,
+    pub restarts:   u64,
+    pub peers:      u64,
+    pub head:       u64,
+    pub pending:    u64,
+    pub queued:     u64,
+    pub pid:        Option<u32>,
+    pub mining:     bool,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockSample {
+    pub timestamp:  u64,
+    pub txs:        usize,
+}
+
+#[derive(Debug, Default)]
+pub struct MetricsState {
+    pub nodes:          Vec<NodeMetrics>,
+    pub submitted:      u64,
+    pub failed:         u64,
+    pub confirmed:      u64,
+    // oldest first, at most BLOCK_HISTORY of them
+    pub blocks:         VecDeque<BlockSample>,
+    latency_counts:     Vec<u64>,
+    latency_sum:        f64,
+    // sent transactions not seen in a block yet, by hash
+    unconfirmed:        HashMap<String, u64>,
+    last_block:         Option<u64>,
+}
+
+impl MetricsState {
+    pub fn new(nodes: usize, sealers: usize) -> MetricsState {
+        MetricsState {
+            nodes:          (0..nodes).map(|id| NodeMetrics { sealer: id < sealers, ..Default::default() }).collect(),
+            latency_counts: vec![0; LATENCY_BUCKETS.len() + 1],
+            ..Default::default()
+        }
+    }
+
+    pub fn submitted(&mut self, hash: Option<&str>, sent_ms: u64) {
+        self.submitted += 1;
+        match hash {
+            Some(hash) => {
+                self.unconfirmed.insert(hash.to_lowercase(), sent_ms);
+            },
+            None => self.failed += 1,
+        }
+    }
+
+    // counts the sent transactions of a new block as confirmed
+    pub fn confirmed(&mut self, tx_hashes: &[String], timestamp: u64) {
+        for h in tx_hashes {
+            if let Some(sent_ms) = self.unconfirmed.remove(&h.to_lowercase()) {
+                let latency = (timestamp * 1000).saturating_sub(sent_ms) as f64 / 1000.0;
+                let bucket = LATENCY_BUCKETS.iter().position(|&b| latency <= b).unwrap_or(LATENCY_BUCKETS.len());
+                self.latency_counts[bucket] += 1;
+                self.latency_sum += latency;
+                self.confirmed += 1;
+            }
+        }
+    }
+
+    pub fn push_block(&mut self, block: BlockSample) {
+        if self.blocks.len() == BLOCK_HISTORY {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(block);
+    }
+
+    // prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, get: &dyn Fn(&NodeMetrics) -> u64| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+            for (id, n) in self.nodes.iter().enumerate() {
+                let role = if n.sealer { "sealer" } else { "peer" };
+                let _ = writeln!(out, "{}{{node=\"{}\",role=\"{}\"}} {}", name, id, role, get(n));
+            }
+        };
+        gauge("ethrunner_node_up", "Whether the node process is running.", &|n| n.up as u64);
+        gauge("ethrunner_node_peers", "Connected peers of the node.", &|n| n.peers);
+        gauge("ethrunner_node_head_block", "Head block number of the node.", &|n| n.head);
+        gauge("ethrunner_txpool_pending", "Pending transactions in the pool of the node.", &|n| n.pending);
+        gauge("ethrunner_txpool_queued", "Queued transactions in the pool of the node.", &|n| n.queued);
+
+        let _ = writeln!(out, "# HELP ethrunner_node_restarts_total Restarts of the node.\n# TYPE ethrunner_node_restarts_total counter");
+        for (id, n) in self.nodes.iter().enumerate() {
+            let _ = writeln!(out, "ethrunner_node_restarts_total{{node=\"{}\"}} {}", id, n.restarts);
+        }
+        for (name, help, v) in [
+            ("ethrunner_tx_submitted_total", "Transactions sent by the runner.", self.submitted),
+            ("ethrunner_tx_failed_total", "Transactions rejected when sent.", self.failed),
+            ("ethrunner_tx_confirmed_total", "Sent transactions seen in a block.", self.confirmed),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, v);
+        }
+
+        let name = "ethrunner_tx_latency_seconds";
+        let _ = writeln!(out, "# HELP {} Time from sending a transaction to the timestamp of its block.\n# TYPE {} histogram", name, name);
+        let mut cumulative = 0;
+        for (i, b) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += self.latency_counts[i];
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, b, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.confirmed);
+        let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, self.latency_sum, name, self.confirmed);
+        out
+    }
+}
+
+// serves the state on http://<listen>/metrics from a thread of its own, until dropped
+// so that the next runner of a sweep can bind the same address, or only keeps it
+// up to date for the dashboard
+pub struct Metrics {
+    pub state:  Arc<Mutex<MetricsState>>,
+    addr:       Option<SocketAddr>,
+    last:       Option<Instant>,
+    stop:       Arc<AtomicBool>,
+    server:     Option<JoinHandle<()>>,
+}
+
+impl Metrics {
+    pub fn start(listen: &str, state: MetricsState) -> io::Result<Metrics> {
+        let listener = TcpListener::bind(listen)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(state));
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let _ = stream.set_nonblocking(false).and_then(|_| serve(stream, &state));
+                        },
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(STOP_POLL),
+                        Err(_) => break,
+                    }
+                }
+            })
+        };
+        Ok(Metrics { state, addr: Some(addr), last: None, stop, server: Some(server) })
+    }
+
+    pub fn local(state: MetricsState) -> Metrics {
+        Metrics {
+            state:  Arc::new(Mutex::new(state)),
+            addr:   None,
+            last:   None,
+            stop:   Arc::new(AtomicBool::new(false)),
+            server: None,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr.expect("Metrics are not served")
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(server) = self.server.take() {
+            server.join().unwrap();
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, state: &Mutex<MetricsState>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8192 {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", state.lock().unwrap().render())
+    } else {
+        ("404 Not Found", String::from("not found\n"))
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body,
+    )
+}
+
+pub fn listen_addr(parsed: &Value) -> Option<String> {
+    let metrics = parsed.get("metrics")?;
+    if !metrics.get("enabled").is_some_and(|v| v.as_bool().unwrap()) {
+        return None;
+    }
+    Some(String::from(metrics.get("listen").map_or("127.0.0.1:9100", |v| v.as_str().unwrap())))
+}
+
+impl NodeRunner {
+    pub(crate) fn with_metrics<F>(&mut self, f: F)
+        where F: FnOnce(&mut MetricsState)
+    {
+        if let Some(m) = self.metrics() {
+            f(&mut m.state.lock().unwrap());
+        }
+    }
+
+    // polls heads, peers and pools of the running nodes and the new blocks of the
+    // first running one, at most once per interval
+    pub(crate) fn update_metrics(&mut self) {
+        match self.metrics() {
+            Some(m) if m.last.is_none_or(|t| t.elapsed() >= UPDATE_INTERVAL) => m.last = Some(Instant::now()),
+            _ => return,
+        }
+        // a node that went away on its own cannot be polled anymore
+        for id in self.running_nodes() {
+            if let Some(status) = self.reap_node(id) {
+                self.with_metrics(|s| {
+                    let n = &mut s.nodes[id];
+                    n.up = false;
+                    n.pid = None;
+                    n.last_error = Some(format!("geth {}", status));
+                });
+                self.save_run_state();
+            }
+        }
+        let running = self.running_nodes();
+        let mut polled = Vec::with_capacity(running.len());
+        for &id in &running {
+            let status = self.eval_json(id, "txpool.status");
+            polled.push((id, self.peer_count(id) as u64, self.block_number(id), json_u64(&status["pending"]), json_u64(&status["queued"])));
+        }
+        let mut blocks = Vec::new();
+        if let Some(&reference) = running.first() {
+            let head = self.block_number(reference);
+            let from = self.metrics().unwrap().state.lock().unwrap().last_block.map_or(head, |b| b + 1);
+            for n in from..=head {
+                let block = self.eval_json(reference, &format!("eth.getBlock({})", n));
+                let hashes: Vec<String> = block["transactions"].as_array().unwrap().iter()
+                    .map(|h| String::from(h.as_str().unwrap()))
+                    .collect();
+                blocks.push((n, hashes, json_u64(&block["timestamp"])));
+            }
+        }
+        self.with_metrics(|s| {
+            for (id, peers, head, pending, queued) in polled {
+                let n = &mut s.nodes[id];
+                n.peers = peers;
+                n.head = head;
+                n.pending = pending;
+                n.queued = queued;
+            }
+            for (n, hashes, timestamp) in blocks {
+                s.confirmed(&hashes, timestamp);
+                s.push_block(BlockSample { timestamp, txs: hashes.len() });
+                s.last_block = Some(n);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrape(addr: SocketAddr, path: &str) -> String {
+        let mut s = TcpStream::connect(addr).unwrap();
+        write!(s, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut resp = String::new();
+        s.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_scrape() {
+        let metrics = Metrics::start("127.0.0.1:0", MetricsState::new(2, 1)).unwrap();
+        {
+            let mut s = metrics.state.lock().unwrap();
+            s.nodes[0].up = true;
+            s.nodes[1].restarts = 2;
+            s.submitted(Some("0xAB"), 1000);
+            s.submitted(None, 1000);
+            s.confirmed(&[String::from("0xab")], 4);
+        }
+        let resp = scrape(metrics.addr(), "/metrics");
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.contains("ethrunner_node_up{node=\"0\",role=\"sealer\"} 1"));
+        assert!(resp.contains("ethrunner_node_up{node=\"1\",role=\"peer\"} 0"));
+        assert!(resp.contains("ethrunner_node_restarts_total{node=\"1\"} 2"));
+        assert!(resp.contains("ethrunner_tx_submitted_total 2"));
+        assert!(resp.contains("ethrunner_tx_failed_total 1"));
+        assert!(resp.contains("ethrunner_tx_latency_seconds_bucket{le=\"2\"} 0"));
+        assert!(resp.contains("ethrunner_tx_latency_seconds_bucket{le=\"5\"} 1"));
+        assert!(resp.contains("ethrunner_tx_latency_seconds_count 1"));
+
+        assert!(scrape(metrics.addr(), "/").starts_with("HTTP/1.1 404"));
+
+        // the address is free again once the endpoint is dropped
+        let addr = metrics.addr();
+        drop(metrics);
+        Metrics::start(&addr.to_string(), MetricsState::new(1, 1)).unwrap();
+    }
+}
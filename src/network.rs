@@ -0,0 +1,320 @@
+use std::io::{self, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use toml::Value;
+
+use crate::init::NodeInitializer;
+use crate::run::NodeRunner;
+use crate::sweep::{self, Cell};
+use crate::utils;
+use crate::wait::{self, POLL_INTERVAL};
+
+// written into the network directory, so that the subcommands work on it too
+pub const CONFIG_FILE: &str = "network.toml";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topology {
+    // every node dials `peers` others picked with a seeded rng
+    Random { peers: usize, seed: u64 },
+    // peers dialed by each node
+    Explicit(Vec<Vec<usize>>),
+}
+
+// collects the same settings as a config file; it does not read config.toml, keys
+// left unset take the values of `config()` or, failing that, the defaults the
+// runner and init fall back to for a missing key, e.g.
+//
+//     let mut net = Network::builder("target/net")
+//         .geth("geth")
+//         .puppeth("puppeth")
+//         .nodes(4, 2)
+//         .topology(Topology::Explicit(vec![vec![1], vec![2], vec![3], vec![0]]))
+//         .block_period(1)
+//         .build()?;
+//     net.init()?;
+//     net.start()?;
+//     let head = net.node(0).block_number();
+pub struct NetworkBuilder {
+    dir:        PathBuf,
+    overrides:  Cell,
+}
+
+impl NetworkBuilder {
+    pub fn geth<P: AsRef<Path>>(self, path: P) -> NetworkBuilder {
+        self.set("bin.geth_dir", path.as_ref().display().to_string())
+    }
+
+    pub fn puppeth<P: AsRef<Path>>(self, path: P) -> NetworkBuilder {
+        self.set("bin.puppeth_dir", path.as_ref().display().to_string())
+    }
+
+    pub fn faketime<P: AsRef<Path>>(self, path: P) -> NetworkBuilder {
+        self.set("bin.faketime_dir", path.as_ref().display().to_string())
+    }
+
+    pub fn nodes(self, count: usize, sealers: usize) -> NetworkBuilder {
+        assert!(sealers <= count, "More sealers than nodes");
+        self.set("node.count", count as i64).set("node.sealer_count", sealers as i64)
+    }
+
+    pub fn topology(self, topology: Topology) -> NetworkBuilder {
+        match topology {
+            Topology::Random { peers, seed } => self
+                .set("node.random_connect", true)
+                .set("node.peer_count", peers as i64)
+                .set("node.seed", seed as i64),
+            Topology::Explicit(conn) => {
+                let conn: Vec<Value> = conn.into_iter()
+                    .map(|peers| Value::Array(peers.into_iter().map(|p| Value::Integer(p as i64)).collect()))
+                    .collect();
+                self.set("node.random_connect", false).set("node.connection", conn)
+            },
+        }
+    }
+
+    // p2p port of node 0, the others follow
+    pub fn base_port(self, port: u16) -> NetworkBuilder {
+        self.set("node.base_port", port as i64)
+    }
+
+    // clique block period of the genesis in seconds
+    pub fn block_period(self, secs: u64) -> NetworkBuilder {
+        self.set("init.block_period", secs as i64)
+    }
+
+    // any other key of the config file, dotted as in experiment files
+    pub fn set<V: Into<Value>>(mut self, key: &str, value: V) -> NetworkBuilder {
+        self.overrides.push((String::from(key), value.into()));
+        self
+    }
+
+    pub fn config(&self) -> Value {
+        let nodes_dir = self.dir.join("nodes");
+        let accounts = nodes_dir.join("accounts.toml").display().to_string();
+        let base: Cell = vec![
+            (String::from("bin.geth_dir"), Value::from("geth")),
+            (String::from("bin.puppeth_dir"), Value::from("puppeth")),
+            (String::from("node.dir"), Value::from(nodes_dir.display().to_string())),
+            (String::from("node.count"), Value::from(4)),
+            (String::from("node.sealer_count"), Value::from(2)),
+            (String::from("node.random_connect"), Value::from(true)),
+            (String::from("node.peer_count"), Value::from(2)),
+            (String::from("init.accounts_dir"), Value::from(accounts.clone())),
+            (String::from("run.accounts_dir"), Value::from(accounts)),
+            (String::from("run.tee"), Value::from(false)),
+            (String::from("test.test"), Value::from(false)),
+        ];
+        let cfg = sweep::apply(&Value::Table(Default::default()), &base);
+        sweep::apply(&cfg, &self.overrides)
+    }
+
+    pub fn build(self) -> io::Result<Network> {
+        let cfg = self.config();
+        fs::create_dir_all(cfg["node"]["dir"].as_str().unwrap())?;
+        let cfg_path = self.dir.join(CONFIG_FILE);
+        File::create(&cfg_path)?.write_all(toml::to_string(&cfg).unwrap().as_bytes())?;
+        Ok(Network {
+            dir:        self.dir,
+            cfg_path,
+            node_count: cfg["node"]["count"].as_integer().unwrap() as usize,
+            runner:     None,
+        })
+    }
+}
+
+// a network embedded in another program, its nodes are stopped when it is dropped
+pub struct Network {
+    dir:        PathBuf,
+    cfg_path:   PathBuf,
+    node_count: usize,
+    runner:     Option<NodeRunner>,
+}
+
+impl Network {
+    pub fn builder<P: AsRef<Path>>(dir: P) -> NetworkBuilder {
+        NetworkBuilder {
+            dir:        dir.as_ref().to_path_buf(),
+            overrides:  Vec::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn config_path(&self) -> &Path {
+        &self.cfg_path
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    fn check_stopped(&self) -> io::Result<()> {
+        if self.runner.is_some() {
+            return Err(io::Error::other("Network is running"));
+        }
+        Ok(())
+    }
+
+    // creates the accounts, the genesis and the datadirs, keeping what is there
+    pub fn init(&mut self) -> io::Result<()> {
+        self.check_stopped()?;
+        if !NodeInitializer::new_with_cfg_file(&self.cfg_path).do_init_node() {
+            return Err(io::Error::other("Init of the network failed"));
+        }
+        Ok(())
+    }
+
+    // runs and connects every node and starts mining on the sealers; what the
+    // runner needs is checked first, so that a missing geth or init is an error
+    pub fn start(&mut self) -> io::Result<()> {
+        self.check_stopped()?;
+        let cfg = utils::read_toml(&self.cfg_path);
+        let geth = cfg["bin"]["geth_dir"].as_str().unwrap();
+        Command::new(geth).arg("version").output()
+            .map_err(|e| io::Error::new(e.kind(), format!("Run {} failed: {}", geth, e)))?;
+        let accounts = utils::load_addrs(Path::new(cfg["run"]["accounts_dir"].as_str().unwrap()))?;
+        if accounts.len() < self.node_count {
+            return Err(io::Error::other(format!("{} account(s) for {} node(s), init the network first", accounts.len(), self.node_count)));
+        }
+        let mut nr = NodeRunner::new_with_cfg_file(&self.cfg_path);
+        nr.start_network();
+        nr.start_mining();
+        self.runner = Some(nr);
+        Ok(())
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.runner.is_some()
+    }
+
+    pub fn node(&mut self, id: usize) -> NodeHandle<'_> {
+        assert!(id < self.node_count, "Network has no node {}", id);
+        NodeHandle {
+            nr: self.runner.as_mut().expect("Network is not started"),
+            id,
+        }
+    }
+
+    // every running node reached block `number`
+    pub fn wait_block(&mut self, number: u64, timeout: Duration) -> bool {
+        let nr = self.runner.as_mut().expect("Network is not started");
+        nr.wait_for(timeout, POLL_INTERVAL, |nr| {
+            let running = nr.running_nodes();
+            nr.reached_block(&running, wait::Quorum::All, number)
+        })
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(mut nr) = self.runner.take() {
+            nr.stop_nodes();
+        }
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// one node of a started network
+pub struct NodeHandle<'a> {
+    nr: &'a mut NodeRunner,
+    id: usize,
+}
+
+impl NodeHandle<'_> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn address(&self) -> String {
+        self.nr.addresses().swap_remove(self.id)
+    }
+
+    pub fn ipc_endpoint(&self) -> String {
+        NodeRunner::ipc_endpoint(self.nr.nodes_dir(), self.id)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.nr.is_running(self.id)
+    }
+
+    // evaluates js in the console of the node, as printed by the console
+    pub fn eval(&mut self, expr: &str) -> String {
+        self.nr.eval(self.id, expr)
+    }
+
+    pub fn eval_json(&mut self, expr: &str) -> serde_json::Value {
+        self.nr.eval_json(self.id, expr)
+    }
+
+    pub fn block_number(&mut self) -> u64 {
+        self.nr.block_number(self.id)
+    }
+
+    pub fn peer_count(&mut self) -> usize {
+        self.nr.peer_count(self.id)
+    }
+
+    // the receipt of a transaction once this node has it in a block
+    pub fn wait_receipt(&mut self, hash: &str, timeout: Duration) -> Option<serde_json::Value> {
+        self.nr.tx_receipt(self.id, hash, timeout)
+    }
+
+    pub fn set_mining(&mut self, on: bool) {
+        self.nr.set_mining(self.id, on);
+    }
+
+    pub fn stop(&mut self) {
+        self.nr.stop_node(self.id);
+    }
+
+    pub fn kill(&mut self) {
+        self.nr.kill_node(self.id);
+    }
+
+    // restarts a stopped node and restores its peerings
+    pub fn start(&mut self) {
+        self.nr.start_node(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn test_build() {
+        let dir = std::env::temp_dir().join(format!("ethrunner-network-{}", std::process::id()));
+        let net = Network::builder(&dir)
+            .geth("/opt/geth")
+            .nodes(3, 1)
+            .topology(Topology::Explicit(vec![vec![1], vec![2], vec![0]]))
+            .block_period(1)
+            .set("verify.enabled", false)
+            .build()
+            .unwrap();
+        let cfg = utils::read_toml(net.config_path());
+        assert_eq!(cfg["bin"]["geth_dir"].as_str(), Some("/opt/geth"));
+        assert_eq!(cfg["node"]["count"].as_integer(), Some(3));
+        assert_eq!(cfg["init"]["block_period"].as_integer(), Some(1));
+        assert_eq!(cfg["verify"]["enabled"].as_bool(), Some(false));
+
+        // the runner reads the network back like any config file
+        let addrs = (0..3).map(|i| format!("{:040x}", i)).collect();
+        utils::save_addrs(addrs, Path::new(cfg["run"]["accounts_dir"].as_str().unwrap())).unwrap();
+        let nr = NodeRunner::new_with_cfg_file(net.config_path());
+        assert_eq!(nr.topology(), vec![vec![1], vec![2], vec![0]]);
+        assert!(!net.is_started());
+
+        drop(net);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::run::NodeRunner;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Partition {
+    // group of each node, nodes outside of all groups form one more group
+    group_of:       Vec<usize>,
+    groups:         usize,
+    start_block:    u64,
+}
+
+#[derive(Debug)]
+pub struct SideReport {
+    pub group:          usize,
+    pub nodes:          Vec<usize>,
+    pub head:           u64,
+    pub hash:           String,
+    // last block of the side that is still canonical after healing
+    pub ancestor:       u64,
+    pub reorg_depth:    u64,
+}
+
+#[derive(Debug)]
+pub struct PartitionReport {
+    pub sides:          Vec<SideReport>,
+    // sides whose blocks were abandoned after healing
+    pub forks:          usize,
+    pub converged:      bool,
+    pub healed_head:    u64,
+}
+
+// the node id part of an enode url, which does not depend on how the peer was dialed
+pub fn enode_id(enode: &str) -> &str {
+    let enode = enode.strip_prefix("enode://").unwrap_or(enode);
+    enode.split('@').next().unwrap()
+}
+
+impl NodeRunner {
+    // splits the network by dropping every peering between nodes of different groups
+    pub fn partition(&mut self, groups: &[Vec<usize>]) {
+        let n = self.node_count();
+        let group_of: Vec<usize> = (0..n)
+            .map(|id| groups.iter().position(|g| g.contains(&id)).unwrap_or(groups.len()))
+            .collect();
+        let start_block = self.running_nodes().into_iter()
+            .map(|id| self.block_number(id))
+            .min()
+            .unwrap_or(0);
+        for x in 0..n {
+            for y in 0..n {
+                if group_of[x] != group_of[y] && self.is_running(x) {
+                    self.remove_peer(x, y);
+                }
+            }
+        }
+        println!("Partitioned network into {:?} at block {}", groups, start_block);
+        *self.partition_state() = Some(Partition {
+            group_of,
+            groups: groups.len() + 1,
+            start_block,
+        });
+    }
+
+    // drops cross-group peers that were dialed again, e.g. through discovery
+    pub fn enforce_partition(&mut self) {
+        let group_of = match *self.partition_state() {
+            Some(ref p) => p.group_of.clone(),
+            None => return,
+        };
+        let n = self.node_count();
+        let ids: Vec<String> = (0..n).map(|id| String::from(enode_id(&self.enode(id)))).collect();
+        for x in self.running_nodes() {
+            let peers = self.eval_json(x, "admin.peers");
+            for peer in peers.as_array().unwrap() {
+                let pid = enode_id(peer["enode"].as_str().unwrap());
+                if let Some(y) = ids.iter().position(|id| id == pid) {
+                    if group_of[x] != group_of[y] {
+                        println!("Partition: dropping re-dialed peering {} - {}", x, y);
+                        self.remove_peer(x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    // keeps the network partitioned for `duration`, then heals it; `idle` is called
+    // to pass the time between two checks for re-dialed peers
+    pub fn partition_for<F>(&mut self, groups: &[Vec<usize>], duration: Duration, heal_timeout: Duration, mut idle: F) -> PartitionReport
+        where F: FnMut(&mut NodeRunner, Instant)
+    {
+        self.partition(groups);
+        let ddl = Instant::now() + duration;
+        while Instant::now() < ddl {
+            idle(self, ddl.min(Instant::now() + Duration::from_secs(1)));
+            self.enforce_partition();
+        }
+        self.heal(heal_timeout).unwrap()
+    }
+
+    // re-adds the configured peerings cut by the partition, waits up to `timeout`
+    // for the nodes to agree on a head, and reports what happened to each side
+    pub fn heal(&mut self, timeout: Duration) -> Option<PartitionReport> {
+        let p = self.partition_state().take()?;
+        let n = self.node_count();
+
+        let mut sides = Vec::new();
+        let mut chains = Vec::new();
+        for g in 0..p.groups {
+            let nodes: Vec<usize> = (0..n).filter(|&id| p.group_of[id] == g && self.is_running(id)).collect();
+            if nodes.is_empty() {
+                continue;
+            }
+            let (head, hash) = self.head(nodes[0]);
+            let chain: Vec<String> = (p.start_block..=head).map(|b| self.block_hash(nodes[0], b)).collect();
+            println!("Partition side {} (nodes {:?}): head #{} {}", g, nodes, head, hash);
+            sides.push(SideReport { group: g, nodes, head, hash, ancestor: head, reorg_depth: 0 });
+            chains.push(chain);
+        }
+
+        for x in 0..n {
+            for y in 0..n {
+                if p.group_of[x] != p.group_of[y] && self.has_peer(x, y) && self.is_running(x) && self.is_running(y) {
+                    self.add_peer(x, y);
+                }
+            }
+        }
+
+        let target = sides.iter().map(|s| s.head).max().unwrap_or(0);
+        let converged = self.wait_converged(target, timeout);
+        let healed_head = sides.first().map_or(0, |s| self.head(s.nodes[0]).0);
+
+        for (side, chain) in sides.iter_mut().zip(chains.iter()) {
+            let rep = side.nodes[0];
+            let mut ancestor = p.start_block;
+            for b in (p.start_block..=side.head).rev() {
+                if self.block_hash(rep, b) == chain[(b - p.start_block) as usize] {
+                    ancestor = b;
+                    break;
+                }
+            }
+            side.ancestor = ancestor;
+            side.reorg_depth = side.head - ancestor;
+        }
+        let report = PartitionReport {
+            forks: sides.iter().filter(|s| s.reorg_depth > 0).count(),
+            sides,
+            converged,
+            healed_head,
+        };
+        report.print();
+        Some(report)
+    }
+
+    fn wait_converged(&mut self, target: u64, timeout: Duration) -> bool {
+        let ddl = Instant::now() + timeout;
+        loop {
+            let heads: Vec<(u64, String)> = self.running_nodes().into_iter()
+                .map(|id| self.head(id))
+                .collect();
+            if heads.iter().all(|h| h.1 == heads[0].1) && heads.first().is_none_or(|h| h.0 >= target) {
+                return true;
+            }
+            if Instant::now() >= ddl {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    pub(crate) fn head(&mut self, id: usize) -> (u64, String) {
+        let head = self.eval_json(id, "eth.getBlock(\"latest\")");
+        (crate::utils::json_u64(&head["number"]), String::from(head["hash"].as_str().unwrap()))
+    }
+
+    // empty when the node has no block at that height, e.g. after a reorg to a
+    // shorter chain, where `eth.getBlock` gives null
+    pub(crate) fn block_hash(&mut self, id: usize, number: u64) -> String {
+        let hash = self.eval_json(id, &format!("(function(b) {{ return b && b.hash }})(eth.getBlock({}))", number));
+        hash.as_str().map_or_else(String::new, String::from)
+    }
+}
+
+impl PartitionReport {
+    pub fn print(&self) {
+        println!(
+            "Partition healed: {}, head #{}, {} side(s) reorganized",
+            if self.converged { "converged" } else { "NOT converged" }, self.healed_head, self.forks,
+        );
+        for s in &self.sides {
+            println!(
+                "  side {} (nodes {:?}): head #{} {}, common ancestor #{}, reorg depth {}",
+                s.group, s.nodes, s.head, s.hash, s.ancestor, s.reorg_depth,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enode_id() {
+        let id = "a979fb575495b8d6db44f750317d0f4622bf4c2aa3365d6af7c284339968eef29b69ad0dce72a4d8db5ebb4968de0e3bec910127f134779fbcb0cb6d3331163c";
+        assert_eq!(enode_id(&format!("enode://{}@127.0.0.1:3001?discport=0", id)), id);
+        assert_eq!(enode_id(&format!("enode://{}@127.0.0.1:41234", id)), id);
+    }
+}
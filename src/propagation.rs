@@ -0,0 +1,272 @@
+use std::io::{self, Write};
+use std::fs::File;
+use std::path::Path;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::analyze::Distribution;
+use crate::utils;
+
+const SUBSCRIBE: &[u8] = br#"{"jsonrpc":"2.0","id":1,"method":"eth_subscribe","params":["newHeads"]}"#;
+
+// a head announced to a node, timestamped when the notification was read
+struct Arrival {
+    node:       usize,
+    hash:       String,
+    number:     u64,
+    time_ms:    u64,
+}
+
+#[cfg(unix)]
+pub(crate) fn connect(endpoint: &str) -> io::Result<std::os::unix::net::UnixStream> {
+    std::os::unix::net::UnixStream::connect(endpoint)
+}
+
+// geth serves ipc on a named pipe, which opens like a file
+#[cfg(windows)]
+pub(crate) fn connect(endpoint: &str) -> io::Result<File> {
+    std::fs::OpenOptions::new().read(true).write(true).open(endpoint)
+}
+
+// subscribes to the heads of one node on a thread of its own, the thread ends
+// when the node goes away or, on the next head, once the monitor was dropped
+fn subscribe(node: usize, endpoint: String, tx: Sender<Arrival>, alive: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut conn = match connect(&endpoint) {
+            Ok(conn) => conn,
+            Err(_) => {
+                alive.store(false, Ordering::SeqCst);
+                return;
+            },
+        };
+        if conn.write_all(SUBSCRIBE).and_then(|_| conn.write_all(b"\n")).is_err() {
+            alive.store(false, Ordering::SeqCst);
+            return;
+        }
+        let msgs = serde_json::Deserializer::from_reader(&conn).into_iter::<serde_json::Value>();
+        for msg in msgs {
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(_) => break,
+            };
+            let head = &msg["params"]["result"];
+            if head.is_null() {
+                continue;
+            }
+            let arrival = Arrival {
+                node,
+                hash:       String::from(head["hash"].as_str().unwrap()),
+                number:     utils::json_u64(&head["number"]),
+                time_ms:    utils::unix_millis(),
+            };
+            if tx.send(arrival).is_err() {
+                break;
+            }
+        }
+        alive.store(false, Ordering::SeqCst);
+    });
+}
+
+pub struct HeadMonitor {
+    nodes:      usize,
+    tx:         Sender<Arrival>,
+    rx:         Receiver<Arrival>,
+    alive:      Vec<Arc<AtomicBool>>,
+    // number and local arrival time on each node of every announced block
+    arrivals:   HashMap<String, (u64, Vec<Option<u64>>)>,
+}
+
+impl HeadMonitor {
+    pub fn new(nodes: usize) -> HeadMonitor {
+        let (tx, rx) = mpsc::channel();
+        HeadMonitor {
+            nodes,
+            tx,
+            rx,
+            alive:      (0..nodes).map(|_| Arc::new(AtomicBool::new(false))).collect(),
+            arrivals:   HashMap::new(),
+        }
+    }
+
+    pub fn is_subscribed(&self, node: usize) -> bool {
+        self.alive[node].load(Ordering::SeqCst)
+    }
+
+    pub fn subscribe(&mut self, node: usize, endpoint: String) {
+        self.alive[node].store(true, Ordering::SeqCst);
+        subscribe(node, endpoint, self.tx.clone(), self.alive[node].clone());
+    }
+
+    pub fn drain(&mut self) {
+        for a in self.rx.try_iter() {
+            let entry = self.arrivals.entry(a.hash).or_insert_with(|| (a.number, vec![None; self.nodes]));
+            if entry.1[a.node].is_none() {
+                entry.1[a.node] = Some(a.time_ms);
+            }
+        }
+    }
+
+    pub fn summarize(&self, topology: &[Vec<usize>]) -> PropagationSummary {
+        summarize(&self.arrivals, topology)
+    }
+
+    pub fn write_csv(&self, path: &Path, topology: &[Vec<usize>]) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "hash,number,node,hops,delay_ms")?;
+        let mut blocks: Vec<_> = self.arrivals.iter().collect();
+        blocks.sort_by_key(|(_, (number, _))| *number);
+        for (hash, (number, seen)) in blocks {
+            let (origin, first) = first_seen(seen);
+            let hops = hop_distances(topology, origin);
+            for (node, t) in seen.iter().enumerate() {
+                if let Some(t) = t {
+                    let h = hops[node].map_or(String::new(), |h| h.to_string());
+                    writeln!(file, "{},{},{},{},{}", hash, number, node, h, t - first)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HopDelay {
+    pub hops:       usize,
+    pub delay_ms:   Distribution,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PropagationSummary {
+    pub blocks:     usize,
+    // delay from the first node seeing a block until half, 90% and all of the
+    // nodes that were subscribed saw it
+    pub to_50:      Distribution,
+    pub to_90:      Distribution,
+    pub to_all:     Distribution,
+    // blocks that never reached every node, e.g. abandoned forks
+    pub incomplete: usize,
+    pub by_hops:    Vec<HopDelay>,
+}
+
+// the node that saw the block first stands in for its sealer
+fn first_seen(seen: &[Option<u64>]) -> (usize, u64) {
+    seen.iter().enumerate()
+        .filter_map(|(node, t)| t.map(|t| (node, t)))
+        .min_by_key(|&(_, t)| t)
+        .unwrap()
+}
+
+// hops from `origin` to every node over the configured peerings, which work both ways
+pub fn hop_distances(topology: &[Vec<usize>], origin: usize) -> Vec<Option<usize>> {
+    let n = topology.len();
+    let mut adj = vec![Vec::new(); n];
+    for (x, peers) in topology.iter().enumerate() {
+        for &y in peers {
+            adj[x].push(y);
+            adj[y].push(x);
+        }
+    }
+    let mut dist = vec![None; n];
+    dist[origin] = Some(0);
+    let mut queue = VecDeque::from(vec![origin]);
+    while let Some(x) = queue.pop_front() {
+        for &y in &adj[x] {
+            if dist[y].is_none() {
+                dist[y] = Some(dist[x].unwrap() + 1);
+                queue.push_back(y);
+            }
+        }
+    }
+    dist
+}
+
+fn summarize(arrivals: &HashMap<String, (u64, Vec<Option<u64>>)>, topology: &[Vec<usize>]) -> PropagationSummary {
+    // only nodes that reported at least one head took part
+    let watched = (0..topology.len())
+        .filter(|&node| arrivals.values().any(|(_, seen)| seen[node].is_some()))
+        .count();
+    let mut to_50 = Vec::new();
+    let mut to_90 = Vec::new();
+    let mut to_all = Vec::new();
+    let mut incomplete = 0;
+    let mut by_hops: Vec<Vec<f64>> = Vec::new();
+    for (_, seen) in arrivals.values() {
+        let (origin, first) = first_seen(seen);
+        let mut delays: Vec<f64> = seen.iter().flatten().map(|t| (t - first) as f64).collect();
+        delays.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // the k-th fastest node out of all watched ones, if the block got that far
+        let reach = |frac: f64| {
+            let k = ((watched as f64 * frac).ceil() as usize).max(1);
+            delays.get(k - 1).copied()
+        };
+        to_50.extend(reach(0.5));
+        to_90.extend(reach(0.9));
+        match reach(1.0) {
+            Some(d) => to_all.push(d),
+            None => incomplete += 1,
+        }
+        let hops = hop_distances(topology, origin);
+        for (node, t) in seen.iter().enumerate() {
+            if let (Some(t), Some(h)) = (t, hops[node]) {
+                if by_hops.len() <= h {
+                    by_hops.resize(h + 1, Vec::new());
+                }
+                by_hops[h].push((t - first) as f64);
+            }
+        }
+    }
+    PropagationSummary {
+        blocks:     arrivals.len(),
+        to_50:      Distribution::from_samples(&to_50),
+        to_90:      Distribution::from_samples(&to_90),
+        to_all:     Distribution::from_samples(&to_all),
+        incomplete,
+        by_hops:    by_hops.iter().enumerate()
+            .filter(|(_, d)| !d.is_empty())
+            .map(|(hops, d)| HopDelay { hops, delay_ms: Distribution::from_samples(d) })
+            .collect(),
+    }
+}
+
+impl PropagationSummary {
+    pub fn print(&self) {
+        println!("Block propagation over {} blocks ({} did not reach every node)", self.blocks, self.incomplete);
+        println!("  to 50% of nodes (ms):  {}", self.to_50);
+        println!("  to 90% of nodes (ms):  {}", self.to_90);
+        println!("  to all nodes (ms):     {}", self.to_all);
+        for h in &self.by_hops {
+            println!("  {} hop(s) (ms):  p50 {:.0} / p90 {:.0}", h.hops, h.delay_ms.p50, h.delay_ms.p90);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hop_distances() {
+        // 0 - 1 - 2, 3 only dials 2
+        let topology = vec![vec![1], vec![2], vec![], vec![2], vec![]];
+        assert_eq!(hop_distances(&topology, 0), vec![Some(0), Some(1), Some(2), Some(3), None]);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let topology = vec![vec![1], vec![2], vec![3], vec![]];
+        let mut arrivals = HashMap::new();
+        arrivals.insert(String::from("0xa"), (1, vec![Some(1000), Some(1100), Some(1300), Some(1600)]));
+        arrivals.insert(String::from("0xb"), (2, vec![None, None, Some(2000), Some(2050)]));
+        let s = summarize(&arrivals, &topology);
+        assert_eq!((s.blocks, s.incomplete), (2, 1));
+        assert_eq!(s.to_all.max, 600.0);
+        assert_eq!(s.to_50.min, 50.0);
+        assert_eq!(s.by_hops[1].delay_ms.mean, 75.0);
+        assert_eq!(s.by_hops[3].delay_ms.mean, 600.0);
+    }
+}
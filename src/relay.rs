@@ -0,0 +1,316 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use rand::Rng;
+use toml::Value;
+
+// delay added to a chunk hit by packet loss, standing for the retransmission timeout of tcp
+const RETRANSMIT_DELAY: Duration = Duration::from_millis(200);
+// how often idle relay threads check whether the relay was stopped
+const STOP_POLL: Duration = Duration::from_millis(100);
+const CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkParams {
+    pub latency:    Duration,
+    pub jitter:     Duration,
+    // bytes per second, unlimited if None
+    pub bandwidth:  Option<u64>,
+    // probability for each chunk to be delayed as if a segment was lost
+    pub loss:       f64,
+    // probability for each chunk to tear the connection down
+    pub disconnect: f64,
+}
+
+impl Default for LinkParams {
+    fn default() -> LinkParams {
+        LinkParams {
+            latency:    Duration::ZERO,
+            jitter:     Duration::ZERO,
+            bandwidth:  None,
+            loss:       0.0,
+            disconnect: 0.0,
+        }
+    }
+}
+
+impl LinkParams {
+    fn from_toml(table: &Value, base: LinkParams) -> LinkParams {
+        let ms = |key: &str, default: Duration| {
+            table.get(key).map_or(default, |v| Duration::from_millis(v.as_integer().unwrap() as u64))
+        };
+        let prob = |key: &str, default: f64| {
+            table.get(key).map_or(default, |v| v.as_float().unwrap())
+        };
+        LinkParams {
+            latency:    ms("latency_ms", base.latency),
+            jitter:     ms("jitter_ms", base.jitter),
+            bandwidth:  table.get("bandwidth_kbps").map_or(base.bandwidth, |v| Some(v.as_integer().unwrap() as u64 * 1000 / 8)),
+            loss:       prob("loss", base.loss),
+            disconnect: prob("disconnect", base.disconnect),
+        }
+    }
+
+    // time at which a chunk read now is handed to the other side
+    fn due(&self, last_due: Instant) -> Instant {
+        let mut rng = rand::thread_rng();
+        let mut delay = self.latency;
+        if !self.jitter.is_zero() {
+            let j = rng.gen_range(-1.0..=1.0) * self.jitter.as_secs_f64();
+            delay = Duration::from_secs_f64((delay.as_secs_f64() + j).max(0.0));
+        }
+        if self.loss > 0.0 && rng.gen_bool(self.loss) {
+            delay += RETRANSMIT_DELAY;
+        }
+        // tcp delivers in order, so a chunk never overtakes the previous one
+        (Instant::now() + delay).max(last_due)
+    }
+}
+
+// link emulation settings, from a config like
+//
+//     [link]
+//     enabled = true
+//     base_port = 4000
+//     latency_ms = 50
+//     jitter_ms = 10
+//     bandwidth_kbps = 10000
+//     loss = 0.01
+//     disconnect = 0.0
+//
+//     [[link.override]]
+//     nodes = [0, 4]
+//     latency_ms = 200
+pub struct LinkConfig {
+    // the relay for the i-th edge listens on base_port+i, or on any free port if 0
+    base_port:  u16,
+    default:    LinkParams,
+    overrides:  Vec<((usize, usize), LinkParams)>,
+}
+
+impl LinkConfig {
+    pub fn new_with_cfg(parsed: &Value) -> Option<LinkConfig> {
+        let link = parsed.get("link")?;
+        if !link.get("enabled").is_some_and(|v| v.as_bool().unwrap()) {
+            return None;
+        }
+        let default = LinkParams::from_toml(link, LinkParams::default());
+        let mut overrides = Vec::new();
+        if let Some(list) = link.get("override") {
+            for o in list.as_array().unwrap() {
+                let nodes = o["nodes"].as_array().unwrap();
+                let edge = (nodes[0].as_integer().unwrap() as usize, nodes[1].as_integer().unwrap() as usize);
+                overrides.push((edge, LinkParams::from_toml(o, default)));
+            }
+        }
+        Some(LinkConfig {
+            base_port:  link.get("base_port").map_or(4000, |v| v.as_integer().unwrap() as u16),
+            default,
+            overrides,
+        })
+    }
+
+    // overrides apply to both directions of a link
+    pub fn params(&self, x: usize, y: usize) -> LinkParams {
+        self.overrides.iter()
+            .find(|(e, _)| *e == (x, y) || *e == (y, x))
+            .map_or(self.default, |(_, p)| *p)
+    }
+}
+
+// forwards each edge of the peer graph through a local port that shapes its traffic
+pub struct Relay {
+    stop:       Arc<AtomicBool>,
+    ports:      HashMap<(usize, usize), u16>,
+    threads:    Vec<JoinHandle<()>>,
+}
+
+impl Relay {
+    // `edges` are (dialer, listener) pairs, `target` gives the port the listener serves on
+    pub fn start<F>(cfg: &LinkConfig, edges: &[(usize, usize)], target: F) -> io::Result<Relay>
+        where F: Fn(usize) -> u16
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut ports = HashMap::new();
+        let mut threads = Vec::new();
+        for (i, &(x, y)) in edges.iter().enumerate() {
+            let port = if cfg.base_port == 0 { 0 } else { cfg.base_port + i as u16 };
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            listener.set_nonblocking(true)?;
+            ports.insert((x, y), listener.local_addr()?.port());
+            let params = cfg.params(x, y);
+            let upstream = target(y);
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || accept_loop(listener, upstream, params, stop)));
+        }
+        Ok(Relay { stop, ports, threads })
+    }
+
+    pub fn port(&self, x: usize, y: usize) -> Option<u16> {
+        self.ports.get(&(x, y)).copied()
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for t in self.threads.drain(..) {
+            t.join().unwrap();
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, upstream: u16, params: LinkParams, stop: Arc<AtomicBool>) {
+    let mut conns: Vec<JoinHandle<()>> = Vec::new();
+    while !stop.load(Ordering::SeqCst) {
+        // pipes of closed connections are done, only the live ones are joined at the end
+        conns.retain(|c| !c.is_finished());
+        match listener.accept() {
+            Ok((down, _)) => {
+                let up = match TcpStream::connect(("127.0.0.1", upstream)) {
+                    Ok(up) => up,
+                    Err(_) => continue,
+                };
+                down.set_nonblocking(false).unwrap();
+                conns.extend(pipe(down.try_clone().unwrap(), up.try_clone().unwrap(), params, stop.clone()));
+                conns.extend(pipe(up, down, params, stop.clone()));
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(STOP_POLL),
+            Err(_) => break,
+        }
+    }
+    for c in conns {
+        c.join().unwrap();
+    }
+}
+
+struct Chunk {
+    data:   Vec<u8>,
+    due:    Instant,
+}
+
+// relays one direction of a connection with a reader and a delaying writer
+fn pipe(mut from: TcpStream, mut to: TcpStream, params: LinkParams, stop: Arc<AtomicBool>) -> Vec<JoinHandle<()>> {
+    let (tx, rx) = mpsc::channel::<Chunk>();
+    from.set_read_timeout(Some(STOP_POLL)).unwrap();
+    let reader = {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut buf = vec![0; CHUNK_SIZE];
+            let mut last_due = Instant::now();
+            while !stop.load(Ordering::SeqCst) {
+                match from.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        last_due = params.due(last_due);
+                        if tx.send(Chunk { data: buf[..n].to_vec(), due: last_due }).is_err() {
+                            break;
+                        }
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => (),
+                    Err(_) => break,
+                }
+            }
+            let _ = from.shutdown(Shutdown::Read);
+        })
+    };
+    let writer = thread::spawn(move || {
+        let mut rng = rand::thread_rng();
+        let mut free_at = Instant::now();
+        loop {
+            let chunk = match rx.recv_timeout(STOP_POLL) {
+                Ok(chunk) => chunk,
+                Err(mpsc::RecvTimeoutError::Timeout) if !stop.load(Ordering::SeqCst) => continue,
+                Err(_) => break,
+            };
+            thread::sleep(chunk.due.saturating_duration_since(Instant::now()));
+            if let Some(bw) = params.bandwidth {
+                free_at = free_at.max(Instant::now()) + Duration::from_secs_f64(chunk.data.len() as f64 / bw as f64);
+                thread::sleep(free_at.saturating_duration_since(Instant::now()));
+            }
+            if params.disconnect > 0.0 && rng.gen_bool(params.disconnect) {
+                break;
+            }
+            if to.write_all(&chunk.data).is_err() {
+                break;
+            }
+        }
+        let _ = to.shutdown(Shutdown::Both);
+    });
+    vec![reader, writer]
+}
+
+// points an enode url at another port on the local host
+pub fn with_port(enode: &str, port: u16) -> String {
+    let id = enode.split('@').next().unwrap();
+    format!("{}@127.0.0.1:{}", id, port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            loop {
+                match s.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => s.write_all(&buf[..n]).unwrap(),
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_latency() {
+        let cfg: Value = toml::from_str("[link]\nenabled = true\nbase_port = 0\nlatency_ms = 100").unwrap();
+        let cfg = LinkConfig::new_with_cfg(&cfg).unwrap();
+        let upstream = echo_server();
+        let relay = Relay::start(&cfg, &[(0, 1)], |_| upstream).unwrap();
+
+        let mut s = TcpStream::connect(("127.0.0.1", relay.port(0, 1).unwrap())).unwrap();
+        let start = Instant::now();
+        s.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        s.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        // the link delays both directions
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        drop(s);
+        drop(relay);
+    }
+
+    #[test]
+    fn test_params() {
+        let cfg: Value = toml::from_str(r#"
+            [link]
+            enabled = true
+            latency_ms = 50
+            bandwidth_kbps = 800
+
+            [[link.override]]
+            nodes = [0, 4]
+            latency_ms = 200
+        "#).unwrap();
+        let cfg = LinkConfig::new_with_cfg(&cfg).unwrap();
+        assert_eq!(cfg.params(1, 2).latency, Duration::from_millis(50));
+        assert_eq!(cfg.params(4, 0).latency, Duration::from_millis(200));
+        assert_eq!(cfg.params(4, 0).bandwidth, Some(100000));
+        assert!(LinkConfig::new_with_cfg(&toml::from_str("[link]\nenabled = false").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_with_port() {
+        assert_eq!(with_port("enode://abcd@127.0.0.1:3001?discport=0", 4002), "enode://abcd@127.0.0.1:4002");
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::collections::HashMap;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::analyze::{ChainAnalyzer, ChainSummary, Distribution};
+use crate::churn::Availability;
+use crate::sealing::SealingReport;
+use crate::verify::ConsistencyReport;
+use crate::txpool::PoolSummary;
+use crate::propagation::PropagationSummary;
+
+// bumped whenever a field of the summary or a column of the csv files changes meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub const SUMMARY_FILE: &str = "summary.json";
+pub const BLOCKS_FILE: &str = "blocks.csv";
+pub const TXS_FILE: &str = "transactions.csv";
+pub const POOL_FILE: &str = "txpool.csv";
+pub const PROPAGATION_FILE: &str = "propagation.csv";
+
+#[derive(Debug, Clone)]
+pub struct TxRecord {
+    pub hash:       Option<String>,
+    pub from:       usize,
+    pub to:         usize,
+    pub nonce:      usize,
+    pub sent_ms:    u64,
+    pub block:      Option<u64>,
+    pub mined_ms:   Option<u64>,
+}
+
+impl TxRecord {
+    // negative when the block timestamp, which has whole seconds, precedes the send time
+    pub fn raw_latency_ms(&self) -> Option<i64> {
+        self.mined_ms.map(|mined| mined as i64 - self.sent_ms as i64)
+    }
+
+    // clamped at 0, `RunSummary::latency_clamped` counts the transactions this hits
+    pub fn latency_ms(&self) -> Option<u64> {
+        self.raw_latency_ms().map(|l| l.max(0) as u64)
+    }
+}
+
+// what the console answers a successful eth.sendTransaction with
+pub fn is_tx_hash(resp: &str) -> bool {
+    resp.len() == 66 && resp.starts_with("0x") && resp[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Counts {
+    pub submitted:  usize,
+    pub failed:     usize,
+    pub confirmed:  usize,
+    pub pending:    usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub schema_version: u32,
+    pub config:         serde_json::Value,
+    pub topology:       Vec<Vec<usize>>,
+    pub seed:           u64,
+    pub geth_version:   String,
+    pub start_time_ms:  u64,
+    pub end_time_ms:    u64,
+    pub counts:         Counts,
+    pub chain:          ChainSummary,
+    // latencies are measured against block timestamps, so they have second resolution
+    pub latency_ms:     Distribution,
+    // confirmed transactions whose block timestamp precedes their send time, their
+    // latency counts as 0
+    #[serde(default)]
+    pub latency_clamped: usize,
+    // only filled for nodes taking part in churn
+    #[serde(default)]
+    pub availability:   Vec<Availability>,
+    #[serde(default)]
+    pub sealing:        Option<SealingReport>,
+    #[serde(default)]
+    pub consistency:    Option<ConsistencyReport>,
+    #[serde(default)]
+    pub pool:           Option<PoolSummary>,
+    #[serde(default)]
+    pub propagation:    Option<PropagationSummary>,
+}
+
+pub struct RunMetadata {
+    pub config:         toml::Value,
+    pub topology:       Vec<Vec<usize>>,
+    pub seed:           u64,
+    pub geth_version:   String,
+    pub start_time_ms:  u64,
+    pub end_time_ms:    u64,
+    pub availability:   Vec<Availability>,
+    pub sealing:        Option<SealingReport>,
+    pub consistency:    Option<ConsistencyReport>,
+    pub pool:           Option<PoolSummary>,
+    pub propagation:    Option<PropagationSummary>,
+}
+
+// matches the sent transactions against the blocks fetched by the analyzer
+pub fn resolve_txs(txs: &mut [TxRecord], ca: &ChainAnalyzer) {
+    let mut mined = HashMap::new();
+    for b in ca.blocks() {
+        for h in &b.tx_hashes {
+            mined.insert(h.to_lowercase(), (b.number, b.timestamp * 1000));
+        }
+    }
+    for tx in txs.iter_mut() {
+        if let Some(ref hash) = tx.hash {
+            if let Some(&(number, ts)) = mined.get(&hash.to_lowercase()) {
+                tx.block = Some(number);
+                tx.mined_ms = Some(ts);
+            }
+        }
+    }
+}
+
+pub fn summarize(meta: RunMetadata, ca: &ChainAnalyzer, txs: &[TxRecord]) -> RunSummary {
+    let failed = txs.iter().filter(|tx| tx.hash.is_none()).count();
+    let confirmed = txs.iter().filter(|tx| tx.block.is_some()).count();
+    let latencies: Vec<f64> = txs.iter()
+        .filter_map(|tx| tx.latency_ms())
+        .map(|l| l as f64)
+        .collect();
+    RunSummary {
+        schema_version: SCHEMA_VERSION,
+        config:         serde_json::to_value(&meta.config).unwrap(),
+        topology:       meta.topology,
+        seed:           meta.seed,
+        geth_version:   meta.geth_version,
+        start_time_ms:  meta.start_time_ms,
+        end_time_ms:    meta.end_time_ms,
+        counts: Counts {
+            submitted:  txs.len(),
+            failed,
+            confirmed,
+            pending:    txs.len() - failed - confirmed,
+        },
+        chain:          ca.summarize(),
+        latency_ms:     Distribution::from_samples(&latencies),
+        latency_clamped: txs.iter().filter(|tx| tx.raw_latency_ms().is_some_and(|l| l < 0)).count(),
+        availability:   meta.availability,
+        sealing:        meta.sealing,
+        consistency:    meta.consistency,
+        pool:           meta.pool,
+        propagation:    meta.propagation,
+    }
+}
+
+// writes the summary and the time series into a fresh directory under `results_dir`
+pub fn write_results(results_dir: &Path, summary: &RunSummary, ca: &ChainAnalyzer, txs: &[TxRecord]) -> io::Result<PathBuf> {
+    let mut dir = results_dir.to_path_buf();
+    dir.push(format!("run-{}", summary.start_time_ms));
+    fs::create_dir_all(&dir)?;
+
+    let file = File::create(dir.join(SUMMARY_FILE))?;
+    serde_json::to_writer_pretty(file, summary)?;
+    ca.write_csv(&dir.join(BLOCKS_FILE))?;
+    write_txs_csv(&dir.join(TXS_FILE), txs)?;
+    Ok(dir)
+}
+
+pub fn load_summary(dir: &Path) -> io::Result<RunSummary> {
+    let file = File::open(dir.join(SUMMARY_FILE))?;
+    let summary: RunSummary = serde_json::from_reader(file)?;
+    if summary.schema_version != SCHEMA_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: unsupported schema version {}", dir.display(), summary.schema_version),
+        ));
+    }
+    Ok(summary)
+}
+
+fn write_txs_csv(path: &Path, txs: &[TxRecord]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "hash,from,to,nonce,sent_ms,block,mined_ms,latency_ms")?;
+    for tx in txs {
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{}",
+            tx.hash.as_deref().unwrap_or(""), tx.from, tx.to, tx.nonce, tx.sent_ms,
+            opt(tx.block), opt(tx.mined_ms), opt(tx.latency_ms()),
+        )?;
+    }
+    Ok(())
+}
+
+fn opt(v: Option<u64>) -> String {
+    v.map_or(String::new(), |v| v.to_string())
+}
+
+pub fn geth_version(geth_dir: &Path) -> String {
+    let output = match Command::new(geth_dir).arg("version").output() {
+        Ok(output) => output,
+        Err(_) => return String::from("unknown"),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|l| l.strip_prefix("Version:"))
+        .map_or(String::from("unknown"), |v| String::from(v.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(hash: Option<&str>, block: Option<u64>, sent_ms: u64, mined_ms: Option<u64>) -> TxRecord {
+        TxRecord {
+            hash: hash.map(String::from),
+            from: 0,
+            to: 1,
+            nonce: 0,
+            sent_ms,
+            block,
+            mined_ms,
+        }
+    }
+
+    #[test]
+    fn test_summary_roundtrip() {
+        let txs = vec![
+            tx(Some("0x1"), Some(1), 1000, Some(3000)),
+            tx(Some("0x3"), Some(1), 3500, Some(3000)),
+            tx(Some("0x2"), None, 1000, None),
+            tx(None, None, 1000, None),
+        ];
+        let meta = RunMetadata {
+            config:         toml::from_str("[node]\ncount = 2").unwrap(),
+            topology:       vec![vec![1], vec![0]],
+            seed:           7,
+            geth_version:   String::from("1.10.17-stable"),
+            start_time_ms:  1000,
+            end_time_ms:    5000,
+            availability:   Vec::new(),
+            sealing:        None,
+            consistency:    None,
+            pool:           None,
+            propagation:    None,
+        };
+        let summary = summarize(meta, &ChainAnalyzer::new(10), &txs);
+        assert_eq!(summary.counts.submitted, 4);
+        assert_eq!(summary.counts.failed, 1);
+        assert_eq!(summary.counts.confirmed, 2);
+        assert_eq!(summary.counts.pending, 1);
+        assert_eq!(summary.latency_ms.max, 2000.0);
+        assert_eq!(summary.latency_ms.min, 0.0);
+        assert_eq!(summary.latency_clamped, 1);
+
+        let dir = std::env::temp_dir().join(format!("results-test-{}", std::process::id()));
+        let run_dir = write_results(&dir, &summary, &ChainAnalyzer::new(10), &txs).unwrap();
+        let loaded = load_summary(&run_dir).unwrap();
+        assert_eq!(loaded.seed, 7);
+        assert_eq!(loaded.config["node"]["count"], 2);
+        let csv = fs::read_to_string(run_dir.join(TXS_FILE)).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_tx_hash() {
+        assert!(is_tx_hash(&format!("0x{}", "ab".repeat(32))));
+        assert!(!is_tx_hash(&format!("0x{}", "ab".repeat(31))));
+        assert!(!is_tx_hash(&format!("0x{}zz", "ab".repeat(31))));
+        assert!(!is_tx_hash("0xError: insufficient funds"));
+    }
+}
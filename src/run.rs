@@ -1,3 +1,6 @@
+use std::fs::OpenOptions;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::rc::{Rc, Weak};
@@ -5,10 +8,23 @@ use std::cell::RefCell;
 use std::process::{self, Command, Stdio};
 use std::thread;
 use std::time;
-use rand::Rng;
-use std::fs::{self, OpenOptions};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use toml::Value;
 
 use crate::utils::{self, Console, ConsoleInteractor, ChildReader, ChildWriter, node_dir};
+use crate::analyze::ChainAnalyzer;
+use crate::results::{self, TxRecord, RunMetadata};
+use crate::scenario::Scenario;
+use crate::partition::Partition;
+use crate::relay::{self, LinkConfig, Relay};
+use crate::churn::{Availability, Churn, ChurnConfig};
+use crate::sealing::{self, SealingEvent};
+use crate::verify::{ConsistencyReport, VerifyConfig};
+use crate::txpool::{PoolConfig, PoolMonitor};
+use crate::propagation::HeadMonitor;
+use crate::metrics::{self, Metrics, MetricsState};
+use crate::state::ManagedNode;
 use crate::NETWORK_ID;
 
 struct Node {
@@ -17,32 +33,90 @@ struct Node {
     address:    String,
     itr:        Option<ConsoleInteractor<ChildReader, ChildWriter>>,
     enode:      Option<String>,
+    child:      Option<process::Child>,
+    mining:     bool,
+    signer:     bool,
+    // seconds the clock of the node is shifted by through faketime
+    clock_skew: Option<f64>,
 }
 
 struct TestConfig {
     n:          usize,
     time_limit: time::Duration,
+    reference:  usize,
+    tps_window: u64,
+    rate:       Option<f64>,
 }
 
+// how long a node is given to shut down after its console was closed
+const STOP_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+// how long a node is given to serve ipc and p2p once its console is up
+pub(crate) const READY_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+// how long each node is given to connect to one of its configured peers
+const PEER_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
 pub struct NodeRunner {
     geth_dir:       PathBuf,
+    faketime_dir:   PathBuf,
     nodes_dir:      PathBuf,
     accounts_dir:   PathBuf,
     nodes:          Vec<Rc<RefCell<Node>>>,
     node_count:     usize,
     sealer_count:   usize,
+    // p2p port of node 0, the others follow
+    base_port:      u16,
     tr:             Option<TEERunner>,
     tf:             Option<TestConfig>,
+    config:         toml::Value,
+    seed:           u64,
+    results_dir:    PathBuf,
+    scenario:       Option<PathBuf>,
+    partition:      Option<Partition>,
+    links:          Option<LinkConfig>,
+    relay:          Option<Relay>,
+    churn:          Option<ChurnConfig>,
+    sealing:        Option<Vec<SealingEvent>>,
+    verify:         Option<VerifyConfig>,
+    txpool:         Option<PoolConfig>,
+    propagation:    bool,
+    metrics:        Option<Metrics>,
+    // geth logs go to a file in the datadir instead of the terminal
+    node_logs:      bool,
+}
 
-    childs:         Vec<process::Child>,
+// state of a test between sending the first transaction and writing the results
+pub(crate) struct Measurement {
+    start_time_ms:  u64,
+    start_block:    u64,
+    reference:      usize,
+    pub ca:         ChainAnalyzer,
+    pub txs:        Vec<TxRecord>,
+    churn:          Option<Churn>,
+    availability:   Vec<Availability>,
+    pub consistency: Option<ConsistencyReport>,
+    pool:           Option<PoolMonitor>,
+    heads:          Option<HeadMonitor>,
+}
+
+impl Measurement {
+    pub(crate) fn push_tx(&mut self, tx: TxRecord) {
+        if let Some(ref mut pool) = self.pool {
+            pool.track(&tx);
+        }
+        self.txs.push(tx);
+    }
 }
 
 impl NodeRunner {
+    #[cfg(test)]
     fn sample(k: i32, n: i32, cur: i32) -> Vec<i32> {
+        Self::sample_with(&mut rand::thread_rng(), k, n, cur)
+    }
+
+    pub(crate) fn sample_with<R: Rng>(rng: &mut R, k: i32, n: i32, cur: i32) -> Vec<i32> {
         if k > n {
             panic!("sample: k>n");
         }
-        let mut rng = rand::thread_rng();
         let mut pool: Vec<i32> = (0..cur).chain(cur+1..n).collect();
         let k = k as usize;
         for i in k..pool.len() {
@@ -54,23 +128,62 @@ impl NodeRunner {
         pool[..k].to_vec()
     }
 
+    // peers dialed by each node, sampled from the seed or as configured
+    pub fn build_topology(parsed: &Value, node_count: usize, seed: u64) -> Vec<Vec<usize>> {
+        let random_conn = parsed["node"].get("random_connect").is_some_and(|v| v.as_bool().unwrap());
+        if random_conn {
+            let peer_count = parsed["node"]["peer_count"].as_integer().unwrap();
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..node_count)
+                .map(|i| {
+                    Self::sample_with(&mut rng, peer_count as i32, node_count as i32, i as i32).into_iter()
+                        .map(|p| p as usize)
+                        .collect()
+                })
+                .collect()
+        } else {
+            parsed["node"]["connection"].as_array().unwrap().iter().take(node_count)
+                .map(|peers| peers.as_array().unwrap().iter().map(|p| p.as_integer().unwrap() as usize).collect())
+                .collect()
+        }
+    }
+
     pub fn new_with_cfg_file(path: &Path) -> NodeRunner {
-        let parsed = utils::read_toml(path);
+        NodeRunner::new_with_cfg(&utils::read_toml(path))
+    }
+
+    pub fn new_with_cfg(parsed: &Value) -> NodeRunner {
         let mut nr = NodeRunner {
             geth_dir:       PathBuf::from_str(parsed["bin"]["geth_dir"].as_str().unwrap()).unwrap(),
+            faketime_dir:   PathBuf::from(parsed["bin"].get("faketime_dir").map_or("faketime", |v| v.as_str().unwrap())),
             nodes_dir:      PathBuf::from_str(parsed["node"]["dir"].as_str().unwrap()).unwrap(),
             accounts_dir:   PathBuf::from_str(parsed["run"]["accounts_dir"].as_str().unwrap()).unwrap(),
             nodes:          Vec::new(),
             node_count:     parsed["node"]["count"].as_integer().unwrap() as usize,
             sealer_count:   parsed["node"]["sealer_count"].as_integer().unwrap() as usize,
+            base_port:      parsed["node"].get("base_port").map_or(3000, |v| v.as_integer().unwrap() as u16),
             tr:             None,
             tf:             None,
-
-            childs:         Vec::new(),
+            config:         parsed.clone(),
+            seed:           parsed["node"].get("seed").map_or_else(rand::random, |v| v.as_integer().unwrap() as u64),
+            results_dir:    PathBuf::from("results"),
+            scenario:       parsed["run"].get("scenario").map(|v| PathBuf::from(v.as_str().unwrap())),
+            partition:      None,
+            links:          LinkConfig::new_with_cfg(parsed),
+            relay:          None,
+            churn:          ChurnConfig::new_with_cfg(parsed),
+            sealing:        None,
+            verify:         VerifyConfig::new_with_cfg(parsed),
+            txpool:         PoolConfig::new_with_cfg(parsed),
+            metrics:        None,
+            node_logs:      parsed["run"].get("node_logs").is_some_and(|v| v.as_bool().unwrap()),
+            propagation:    parsed.get("propagation").and_then(|p| p.get("enabled")).is_some_and(|v| v.as_bool().unwrap()),
         };
         nr.nodes.reserve(nr.node_count);
         let addrs = utils::load_addrs(&nr.accounts_dir).unwrap();
-        for (i, address) in addrs.into_iter().enumerate() {
+        // accounts beyond node.count belong to nodes `add-node` started detached,
+        // `stop` and `remove-node` take care of them
+        for (i, address) in addrs.into_iter().take(nr.node_count).enumerate() {
             nr.nodes.push(Rc::new(RefCell::new(
                 Node {
                     peers:      Vec::new(),
@@ -78,39 +191,32 @@ impl NodeRunner {
                     address,
                     itr:        None,
                     enode:      None,
+                    child:      None,
+                    mining:     false,
+                    signer:     i < nr.sealer_count,
+                    clock_skew: None,
                 }
             )));
         }
-        let mut random_conn = false;
-        if let Some(rc) = parsed["node"].get("random_connect") {
-            random_conn = rc.as_bool().unwrap();
-        }
-        if random_conn {
-            let peer_count = parsed["node"]["peer_count"].as_integer().unwrap();
-            for i in 0..nr.nodes.len() {
-                let pids = Self::sample(peer_count as i32, nr.node_count as i32, i as i32);
-                for pid in pids {
-                    nr.nodes[i].borrow_mut().peers.push(
-                        Rc::downgrade(&nr.nodes[pid as usize])
-                    );
-                }
-            }
-        } else {
-            let conn = parsed["node"]["connection"].as_array().unwrap();
-            for i in 0..nr.node_count {
-                let peers = conn[i].as_array().unwrap();
-                for p in peers {
-                    let pid = p.as_integer().unwrap() as usize;
-                    nr.nodes[i].borrow_mut().peers.push(
-                        Rc::downgrade(&nr.nodes[pid])
-                    );
-                }
+        let topology = Self::build_topology(parsed, nr.node_count, nr.seed);
+        for (i, peers) in topology.into_iter().enumerate().take(nr.nodes.len()) {
+            for pid in peers {
+                nr.nodes[i].borrow_mut().peers.push(
+                    Rc::downgrade(&nr.nodes[pid])
+                );
             }
         }
+        // sized from the nodes loaded, which is what the runner indexes it with
+        if let Some(listen) = metrics::listen_addr(parsed) {
+            let state = MetricsState::new(nr.nodes.len(), nr.sealer_count);
+            let m = Metrics::start(&listen, state).expect("Start metrics endpoint failed");
+            println!("Serving metrics on http://{}/metrics", m.addr());
+            nr.metrics = Some(m);
+        }
         if let Some(tee) = parsed["run"].get("tee") {
             let tee = tee.as_bool().unwrap();
             if tee {
-                nr.tr = Some(TEERunner::new_with_cfg_file(path));
+                nr.tr = Some(TEERunner::new_with_cfg(parsed));
             }
         }
 
@@ -121,6 +227,9 @@ impl NodeRunner {
                     TestConfig {
                         n:          parsed["test"]["n"].as_integer().unwrap() as usize,
                         time_limit: time::Duration::from_secs(parsed["test"]["period"].as_integer().unwrap() as u64),
+                        reference:  parsed["test"].get("reference_node").map_or(0, |v| v.as_integer().unwrap() as usize),
+                        tps_window: parsed["test"].get("tps_window").map_or(10, |v| v.as_integer().unwrap() as u64),
+                        rate:       parsed["test"].get("rate").map(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64)).unwrap()),
                     }
                 );
             }
@@ -129,8 +238,63 @@ impl NodeRunner {
         nr
     }
 
-    // consumes the value to avoid multiple calls on this function
-    pub fn do_run_nodes(mut self) {
+    pub fn set_results_dir(&mut self, dir: PathBuf) {
+        self.results_dir = dir;
+    }
+
+    pub fn set_scenario(&mut self, path: PathBuf) {
+        self.scenario = Some(path);
+    }
+
+    pub(crate) fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // consumes the value to avoid multiple calls on this function,
+    // returns the results directory when a test was run
+    pub fn do_run_nodes(mut self) -> Option<PathBuf> {
+        self.start_network();
+        if let Some(path) = self.scenario.take() {
+            let scenario = Scenario::new_with_file(&path);
+            if scenario.start_mining {
+                self.start_mining();
+            }
+            let (dir, passed) = self.run_scenario(&scenario);
+            self.stop_nodes();
+            if !passed {
+                println!("Scenario failed, results written to {}", dir.display());
+                process::exit(1);
+            }
+            return Some(dir);
+        }
+        self.start_mining();
+        let tf = self.tf.take();
+        if let Some(tf) = tf {
+            let (dir, consistent) = self.test_send_txs(&tf);
+            self.stop_nodes();
+            if !consistent {
+                println!("Nodes did not converge, results written to {}", dir.display());
+                process::exit(1);
+            }
+            Some(dir)
+        } else if self.metrics.is_some() {
+            // keeps the metrics fresh while the network runs
+            loop {
+                self.update_metrics();
+                thread::sleep(time::Duration::from_secs(1));
+            }
+        } else {
+            loop {
+                thread::park();
+            }
+        }
+    }
+
+    pub(crate) fn start_network(&mut self) {
         if let Some(ref mut tr) = self.tr {
             tr.do_init_tee();
         }
@@ -139,60 +303,323 @@ impl NodeRunner {
             self.run_node(i);
         }
         self.connect_nodes();
-        self.start_mining();
-        let tf = self.tf.take();
-        if let Some(tf) = tf {
-            self.test_send_txs(tf.n, tf.time_limit);
-        } else {
-            loop {}
+    }
+
+    pub(crate) fn stop_nodes(&mut self) {
+        for i in 0..self.nodes.len() {
+            if self.is_running(i) {
+                self.stop_node(i);
+            }
+        }
+    }
+
+    pub(crate) fn is_running(&self, id: usize) -> bool {
+        self.nodes[id].borrow().child.is_some()
+    }
+
+    pub(crate) fn running_nodes(&self) -> Vec<usize> {
+        (0..self.nodes.len()).filter(|&id| self.is_running(id)).collect()
+    }
+
+    // closes the console so that geth shuts down cleanly, killing it if it hangs
+    pub(crate) fn stop_node(&mut self, id: usize) {
+        {
+            let mut node = self.nodes[id].borrow_mut();
+            let mut child = node.child.take().expect("Node is not running");
+            node.itr = None;
+            let ddl = time::Instant::now() + STOP_TIMEOUT;
+            while child.try_wait().unwrap().is_none() {
+                if time::Instant::now() >= ddl {
+                    child.kill().unwrap();
+                    child.wait().unwrap();
+                    break;
+                }
+                thread::sleep(time::Duration::from_millis(100));
+            }
         }
+        self.with_metrics(|s| {
+            s.nodes[id].up = false;
+            s.nodes[id].pid = None;
+        });
+        self.log_sealing(format!("stop node {}", id));
+        self.save_run_state();
+    }
+
+    // kills the node without giving it a chance to persist its state
+    pub(crate) fn kill_node(&mut self, id: usize) {
+        {
+            let mut node = self.nodes[id].borrow_mut();
+            let mut child = node.child.take().expect("Node is not running");
+            node.itr = None;
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+        self.with_metrics(|s| {
+            s.nodes[id].up = false;
+            s.nodes[id].pid = None;
+        });
+        self.log_sealing(format!("kill node {}", id));
+        self.save_run_state();
+    }
+
+    // notices a node whose process exited on its own, e.g. after a crash
+    pub(crate) fn reap_node(&mut self, id: usize) -> Option<process::ExitStatus> {
+        let mut node = self.nodes[id].borrow_mut();
+        let status = node.child.as_mut()?.try_wait().unwrap()?;
+        node.child = None;
+        node.itr = None;
+        Some(status)
+    }
+
+    // restarts a stopped node and restores the peerings it takes part in, a sealer
+    // that was mining before goes on mining
+    pub(crate) fn start_node(&mut self, id: usize) {
+        self.rejoin(id);
+        self.with_metrics(|s| s.nodes[id].restarts += 1);
+    }
+
+    // runs a stopped node again with its miner and peerings, without counting a restart
+    fn rejoin(&mut self, id: usize) {
+        self.run_node(id);
+        if self.is_mining(id) {
+            self.eval(id, "miner.start()");
+        }
+        for j in 0..self.nodes.len() {
+            if j == id || !self.is_running(j) {
+                continue;
+            }
+            if self.has_peer(id, j) {
+                self.add_peer(id, j);
+            }
+            if self.has_peer(j, id) {
+                self.add_peer(j, id);
+            }
+        }
+        self.log_sealing(format!("start node {}", id));
+    }
+
+    // restarts the node with its clock shifted by `skew` seconds, 0 restores the real
+    // clock; not a restart as far as the metrics go
+    pub(crate) fn set_clock_skew(&mut self, id: usize, skew: f64) {
+        if self.is_running(id) {
+            self.stop_node(id);
+        }
+        self.nodes[id].borrow_mut().clock_skew = if skew == 0.0 { None } else { Some(skew) };
+        self.rejoin(id);
+    }
+
+    // whether `y` is one of the configured peers of `x`
+    pub(crate) fn has_peer(&self, x: usize, y: usize) -> bool {
+        self.nodes[x].borrow().peers.iter().any(|p| p.upgrade().unwrap().borrow().id == y)
+    }
+
+    pub(crate) fn add_peer(&mut self, x: usize, y: usize) {
+        let mut enode = self.nodes[y].borrow().enode.clone().unwrap();
+        // configured edges are dialed through their relay when links are emulated
+        if let Some(port) = self.relay.as_ref().and_then(|r| r.port(x, y)) {
+            enode = relay::with_port(&enode, port);
+        }
+        self.eval(x, &format!("admin.addPeer(\"{}\")", enode));
+    }
+
+    pub(crate) fn remove_peer(&mut self, x: usize, y: usize) {
+        let enode = self.nodes[y].borrow().enode.clone().unwrap();
+        self.eval(x, &format!("admin.removePeer(\"{}\")", enode));
+    }
+
+    pub(crate) fn set_mining(&mut self, id: usize, on: bool) {
+        self.eval(id, if on { "miner.start()" } else { "miner.stop()" });
+        self.nodes[id].borrow_mut().mining = on;
+        self.with_metrics(|s| s.nodes[id].mining = on);
+        self.log_sealing(format!("{} mining on node {}", if on { "start" } else { "stop" }, id));
+        self.save_run_state();
+    }
+
+    pub(crate) fn is_mining(&self, id: usize) -> bool {
+        self.nodes[id].borrow().mining
+    }
+
+    // whether the node is in the clique signer set, as voted at runtime
+    pub(crate) fn is_signer(&self, id: usize) -> bool {
+        self.nodes[id].borrow().signer
+    }
+
+    pub(crate) fn signer_count(&self) -> usize {
+        (0..self.nodes.len()).filter(|&id| self.is_signer(id)).count()
+    }
+
+    pub(crate) fn set_signer(&mut self, id: usize, signer: bool) {
+        self.nodes[id].borrow_mut().signer = signer;
+        self.with_metrics(|s| s.nodes[id].sealer = signer);
+        self.log_sealing(format!("{} signer {}", if signer { "add" } else { "remove" }, id));
+        self.save_run_state();
+    }
+
+    pub(crate) fn addresses(&self) -> Vec<String> {
+        self.nodes.iter().map(|node| node.borrow().address.clone()).collect()
+    }
+
+    pub(crate) fn metrics(&mut self) -> Option<&mut Metrics> {
+        self.metrics.as_mut()
     }
 
-    fn start_mining(&mut self) {
+    // keeps the metrics state up to date even if it is not served
+    pub(crate) fn track_metrics(&mut self) -> &Metrics {
+        let (nodes, sealers) = (self.node_count, self.sealer_count);
+        self.metrics.get_or_insert_with(|| Metrics::local(MetricsState::new(nodes, sealers)))
+    }
+
+    pub(crate) fn managed_node(&self, id: usize) -> ManagedNode {
+        let node = self.nodes[id].borrow();
+        ManagedNode {
+            id,
+            running:    node.child.is_some(),
+            pid:        node.child.as_ref().map(|c| c.id()),
+            sealer:     node.signer,
+            mining:     node.mining,
+            ipc:        Self::ipc_endpoint(&self.nodes_dir, id),
+            enode:      node.enode.clone(),
+            detached:   false,
+        }
+    }
+
+    pub(crate) fn set_node_logs(&mut self, on: bool) {
+        self.node_logs = on;
+    }
+
+    pub(crate) fn nodes_dir(&self) -> &Path {
+        &self.nodes_dir
+    }
+
+    pub(crate) fn verify_config(&self) -> Option<VerifyConfig> {
+        self.verify
+    }
+
+    pub(crate) fn sealing_log(&mut self) -> Option<&mut Vec<SealingEvent>> {
+        self.sealing.as_mut()
+    }
+
+    // [test].reference_node, also when the config runs no plain test
+    pub(crate) fn reference_node(&self) -> usize {
+        self.config.get("test").and_then(|t| t.get("reference_node")).map_or(0, |v| v.as_integer().unwrap() as usize)
+    }
+
+    // evaluates a console expression on a running node
+    pub(crate) fn eval(&mut self, id: usize, expr: &str) -> String {
+        let mut node = self.nodes[id].borrow_mut();
+        node.itr.as_mut().expect("Node is not running").send_with_resp(expr.as_bytes())
+    }
+
+    pub(crate) fn eval_json(&mut self, id: usize, expr: &str) -> serde_json::Value {
+        let mut node = self.nodes[id].borrow_mut();
+        node.itr.as_mut().expect("Node is not running").send_for_json(expr)
+    }
+
+    pub(crate) fn enode(&self, id: usize) -> String {
+        self.nodes[id].borrow().enode.clone().unwrap()
+    }
+
+    pub(crate) fn partition_state(&mut self) -> &mut Option<Partition> {
+        &mut self.partition
+    }
+
+    pub(crate) fn start_mining(&mut self) {
         for i in 0..self.sealer_count {
             let mut node = self.nodes[i].borrow_mut();
             node.itr.as_mut().unwrap().send_with_resp(b"miner.start()");
             node.itr.as_mut().unwrap().send_with_resp(b"clique.getSigners()");
             node.itr.as_mut().unwrap().send_with_resp(b"eth.accounts[0]");
             node.itr.as_mut().unwrap().send_with_resp(b"admin.peers");
+            node.mining = true;
         }
+        let sealers = self.sealer_count;
+        self.with_metrics(|s| s.nodes.iter_mut().take(sealers).for_each(|n| n.mining = true));
+        self.save_run_state();
     }
 
     fn connect_nodes(&mut self) {
-        for node in &mut self.nodes {
-            let mut node = node.borrow_mut();
-            for i in 0..node.peers.len() {
-                let p = &node.peers[i];
-                let prc = p.upgrade().unwrap();
-                let pmut = prc.borrow();
-                let enode = pmut.enode.as_ref().unwrap().clone();
-                node.itr.as_mut().unwrap().send_with_resp(
-                    format!("admin.addPeer(\"{}\")", enode).as_bytes()
-                );
+        let topology = self.topology();
+        if let Some(ref links) = self.links {
+            let edges: Vec<(usize, usize)> = topology.iter().enumerate()
+                .flat_map(|(x, peers)| peers.iter().map(move |&y| (x, y)))
+                .collect();
+            let base_port = self.base_port;
+            self.relay = Some(Relay::start(links, &edges, |id| base_port + id as u16).expect("Start link relay failed"));
+        }
+        for (x, peers) in topology.iter().enumerate() {
+            for &y in peers {
+                self.add_peer(x, y);
+            }
+        }
+        for (x, peers) in topology.iter().enumerate() {
+            if !peers.is_empty() && !self.wait_peers(x, 1, PEER_TIMEOUT) {
+                println!("Node {} has no peer after {}s", x, PEER_TIMEOUT.as_secs());
             }
         }
     }
 
+    // without `console` geth runs on its own, as nodes added to a live network do
+    pub(crate) fn geth_command(&self, id: usize, console: bool) -> Command {
+        let node = self.nodes[id].borrow();
+        let mut geth = match node.clock_skew {
+            Some(skew) => {
+                let mut cmd = Command::new(&self.faketime_dir);
+                cmd.arg("-f").arg(format!("{:+}s", skew)).arg(&self.geth_dir);
+                cmd
+            },
+            None => Command::new(&self.geth_dir),
+        };
+        geth.arg(format!("--datadir={}", node_dir(&self.nodes_dir, id)))
+            .arg(format!("--networkid={}", NETWORK_ID))
+            .arg(format!("--port={}", self.p2p_port(id)));
+        if console {
+            geth.arg("console");
+        }
+        geth.arg(format!("--ipcpath={}", Self::ipc_endpoint(&self.nodes_dir, id)))
+            .arg(format!("--unlock={}", node.address))
+            .arg(format!("--password={}", utils::password_file(&self.nodes_dir).display()));
+        // emulated links only exist between configured peers, discovery would bypass them
+        if self.links.is_some() {
+            geth.arg("--nodiscover");
+        }
+        geth
+    }
+
+    // what `do_run_nodes` would start and wire up, without starting anything
+    pub fn print_commands(&self) {
+        for id in 0..self.nodes.len() {
+            println!("{:?}", self.geth_command(id, true));
+        }
+        for (x, peers) in self.topology().iter().enumerate() {
+            for y in peers {
+                println!("node {}: admin.addPeer(<enode of node {}>)", x, y);
+            }
+        }
+        for id in (0..self.nodes.len()).filter(|&id| self.is_signer(id)) {
+            println!("node {}: miner.start()", id);
+        }
+    }
+
     // runs the node and opens its console interactor
     fn run_node(&mut self, ith: usize) {
+        let mut geth = self.geth_command(ith, true);
         let mut node = self.nodes[ith].borrow_mut();
         // let output = OpenOptions::new()
         //                 .write(true)
         //                 .create(true)
         //                 .open(format!("node{}.txt", ith))
         //                 .unwrap();
-        let mut geth = Command::new(&self.geth_dir)
-            .arg(format!("--datadir={}", node_dir(&self.nodes_dir, node.id)))
-            .arg(format!("--networkid={}", NETWORK_ID))
-            .arg(format!("--port={}", 3000+node.id))
-            .arg("console")
-            .arg(format!("--ipcpath={}", Self::ipc_path(node.id)))
-            .arg(format!("--unlock={}", node.address))
-            .arg(format!("--password=password"))
-            // .arg(format!("2> out{}.txt", ith))
+        if self.node_logs {
+            let log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(Self::log_path(&self.nodes_dir, node.id))
+                .unwrap();
+            geth.stderr(log);
+        }
+        let mut geth = geth
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            // .stderr(Stdio::piped())
             .spawn()
             .unwrap();
         let console = Console::
@@ -208,42 +635,206 @@ impl NodeRunner {
         let resp = node.itr.as_mut().unwrap().send_with_resp(test_msg);
         assert_eq!(resp[2..].to_uppercase(), node.address.clone().to_uppercase());
 
-        match node.enode {
-            None => {
-                let enode = node.itr.as_mut().unwrap().send_with_resp(b"admin.nodeInfo.enode");
-                node.enode = Some(enode);
-            },
-            Some(_) => panic!("Initialzied enode"),
-        }
+        // the enode is kept across restarts since the node key lives in the datadir
+        let enode = node.itr.as_mut().unwrap().send_with_resp(b"admin.nodeInfo.enode");
+        node.enode = Some(enode);
 
-        self.childs.push(geth);
+        node.child = Some(geth);
+        drop(node);
+        if !self.wait_ready(ith, READY_TIMEOUT) {
+            panic!("Node {} did not become ready within {}s", ith, READY_TIMEOUT.as_secs());
+        }
+        let pid = self.nodes[ith].borrow().child.as_ref().unwrap().id();
+        self.with_metrics(|s| {
+            s.nodes[ith].up = true;
+            s.nodes[ith].pid = Some(pid);
+        });
+        self.save_run_state();
     }
 
-    fn test_send_txs(&mut self, n: usize, time_limit: time::Duration) {
+    // returns the results directory and whether the nodes converged if verified
+    fn test_send_txs(&mut self, tf: &TestConfig) -> (PathBuf, bool) {
         let before = self.get_tx_cnt();
         println!("Transaction counts before sending tx: {:?}", before);
-        let ddl = time::Instant::now() + time_limit;
-        self.send_txs(n, ddl);
-        thread::sleep(ddl - time::Instant::now());
+        let mut m = self.begin_measurement(tf.reference, tf.tps_window);
+        let mut nonces = before.clone();
+        let ddl = time::Instant::now() + tf.time_limit;
+        self.send_txs(tf.n, ddl, tf.rate, &mut m, &mut nonces);
+        // sample the reference head so that reorgs can be detected, until the deadline
+        // or until every sent transaction left the pools
+        let timeout = ddl.saturating_duration_since(time::Instant::now());
+        self.wait_for(timeout, time::Duration::from_secs(1), |nr| {
+            nr.observe_head(&mut m);
+            nr.measure_step(&mut m, &mut nonces);
+            let running = nr.running_nodes();
+            !m.txs.is_empty() && nr.txpool_drained(&running)
+        });
+        // the pools may drain before the reference node imported the last block
+        let last = m.txs.iter().rev().find_map(|tx| tx.hash.clone());
+        if let Some(hash) = last.filter(|_| self.is_running(m.reference)) {
+            let timeout = ddl.saturating_duration_since(time::Instant::now());
+            self.tx_receipt(m.reference, &hash, timeout);
+        }
+        self.end_churn(&mut m);
         let after = self.get_tx_cnt();
         println!("Transaction counts after sending tx: {:?}", after);
         let dif: Vec<usize> = (0..self.nodes.len()).map(|i| after[i]-before[i]).collect();
         println!("Transaction committed for each node: {:?}", dif);
         println!("Total committed transactions: {}", dif.into_iter().sum::<usize>());
+
+        if let Some(cfg) = self.verify {
+            m.consistency = Some(self.verify_chain(&cfg));
+        }
+        let consistent = m.consistency.as_ref().is_none_or(|c| c.converged);
+        (self.finish_measurement(m), consistent)
+    }
+
+    pub(crate) fn begin_measurement(&mut self, reference: usize, tps_window: u64) -> Measurement {
+        self.sealing = Some(Vec::new());
+        self.log_sealing(String::from("start"));
+        let churn = self.churn.take().map(|cfg| {
+            let churn = self.begin_churn(&cfg, reference);
+            self.churn = Some(cfg);
+            churn
+        });
+        Measurement {
+            start_time_ms:  utils::unix_millis(),
+            start_block:    self.block_number(reference),
+            reference,
+            ca:             ChainAnalyzer::new(tps_window),
+            txs:            Vec::new(),
+            churn,
+            availability:   Vec::new(),
+            consistency:    None,
+            pool:           self.txpool.map(|cfg| PoolMonitor::new(cfg, self.nodes.len())),
+            heads:          if self.propagation { Some(HeadMonitor::new(self.nodes.len())) } else { None },
+        }
+    }
+
+    pub(crate) fn observe_head(&mut self, m: &mut Measurement) {
+        if self.is_running(m.reference) {
+            m.ca.observe_head(self.nodes[m.reference].borrow_mut().itr.as_mut().unwrap());
+        }
+    }
+
+    // everything sampled while the load runs: churn, pools, block arrivals and metrics
+    pub(crate) fn measure_step(&mut self, m: &mut Measurement, nonces: &mut [usize]) {
+        self.update_metrics();
+        if let Some(mut churn) = m.churn.take() {
+            self.churn_tick(&mut churn, nonces);
+            m.churn = Some(churn);
+        }
+        if let Some(mut pool) = m.pool.take() {
+            self.sample_pools(&mut pool);
+            m.pool = Some(pool);
+        }
+        if let Some(ref mut heads) = m.heads {
+            // (re)subscribes to nodes that started since the last step
+            for id in 0..self.nodes.len() {
+                if self.is_running(id) && !heads.is_subscribed(id) {
+                    heads.subscribe(id, Self::ipc_endpoint(&self.nodes_dir, id));
+                }
+            }
+            heads.drain();
+        }
+    }
+
+    fn end_churn(&mut self, m: &mut Measurement) {
+        if let Some(churn) = m.churn.take() {
+            m.availability = self.finish_churn(churn);
+        }
+    }
+
+    // analyzes the chain of the reference node and writes the results
+    pub(crate) fn finish_measurement(&mut self, mut m: Measurement) -> PathBuf {
+        self.end_churn(&mut m);
+        // the chain is the same on every node once they converged
+        if !self.is_running(m.reference) {
+            let other = (0..self.nodes.len()).find(|&id| self.is_running(id)).expect("No node is running, the chain cannot be measured");
+            println!("Reference node {} is stopped, measuring the chain on node {}", m.reference, other);
+            m.reference = other;
+        }
+        let end_block = self.block_number(m.reference);
+        m.ca.fetch(self.nodes[m.reference].borrow_mut().itr.as_mut().unwrap(), m.start_block, end_block);
+        m.ca.summarize().print();
+        let end_time_ms = utils::unix_millis();
+        let events = self.sealing.take().unwrap_or_default();
+        let sealing = sealing::report(&events, m.ca.blocks(), end_time_ms);
+        sealing.print();
+
+        results::resolve_txs(&mut m.txs, &m.ca);
+        let topology = self.topology();
+        let meta = RunMetadata {
+            config:         self.config.clone(),
+            topology:       topology.clone(),
+            seed:           self.seed,
+            geth_version:   results::geth_version(&self.geth_dir),
+            start_time_ms:  m.start_time_ms,
+            end_time_ms,
+            availability:   m.availability,
+            sealing:        Some(sealing),
+            consistency:    m.consistency,
+            pool:           m.pool.as_ref().map(|p| p.summarize()),
+            propagation:    m.heads.as_mut().map(|h| {
+                h.drain();
+                h.summarize(&topology)
+            }),
+        };
+        let summary = results::summarize(meta, &m.ca, &m.txs);
+        if let Some(ref pool) = summary.pool {
+            pool.print();
+        }
+        if let Some(ref propagation) = summary.propagation {
+            propagation.print();
+        }
+        let dir = results::write_results(&self.results_dir, &summary, &m.ca, &m.txs)
+            .expect("Write results failed");
+        if let Some(ref pool) = m.pool {
+            pool.write_csv(&dir.join(results::POOL_FILE)).expect("Write results failed");
+        }
+        if let Some(ref heads) = m.heads {
+            heads.write_csv(&dir.join(results::PROPAGATION_FILE), &topology).expect("Write results failed");
+        }
+        println!("Results written to {}", dir.display());
+        dir
+    }
+
+    pub(crate) fn topology(&self) -> Vec<Vec<usize>> {
+        self.nodes.iter()
+            .map(|node| node.borrow().peers.iter().map(|p| p.upgrade().unwrap().borrow().id).collect())
+            .collect()
     }
 
-    fn send_txs(&mut self, n: usize, ddl: time::Instant) {
+    pub(crate) fn block_number(&mut self, id: usize) -> u64 {
+        let mut node = self.nodes[id].borrow_mut();
+        let resp = node.itr.as_mut().unwrap().send_with_resp(b"eth.blockNumber");
+        str::parse(&resp).unwrap()
+    }
+
+    // sends `n` rounds of one transaction per running node, at most `rate` rounds per second
+    fn send_txs(&mut self, n: usize, ddl: time::Instant, rate: Option<f64>, m: &mut Measurement, nonces: &mut [usize]) {
+        let start = time::Instant::now();
         for i in 0..n {
+            if let Some(rate) = rate {
+                let next = start + time::Duration::from_secs_f64(i as f64 / rate);
+                thread::sleep(next.min(ddl).saturating_duration_since(time::Instant::now()));
+            }
             if time::Instant::now() >= ddl {
                 break;
             }
-            for j in 0..self.nodes.len() {
-                self.send_tx(j, (j+1)%self.nodes.len(), i)
+            self.measure_step(m, nonces);
+            for j in self.running_nodes() {
+                m.push_tx(self.send_tx(j, (j+1)%self.nodes.len(), nonces[j]));
+                nonces[j] += 1;
             }
         }
     }
 
-    fn get_tx_cnt(&mut self) -> Vec<usize> {
+    pub(crate) fn pending_nonce(&mut self, id: usize) -> usize {
+        self.eval(id, "eth.getTransactionCount(eth.accounts[0], \"pending\")").parse().unwrap()
+    }
+
+    pub(crate) fn get_tx_cnt(&mut self) -> Vec<usize> {
         let mut res = Vec::with_capacity(self.nodes.len());
         for node in &mut self.nodes {
             let mut node = node.borrow_mut();
@@ -255,7 +846,7 @@ impl NodeRunner {
         res
     }
 
-    fn send_tx(&mut self, x: usize, y: usize, nonce: usize) {
+    pub(crate) fn send_tx(&mut self, x: usize, y: usize, nonce: usize) -> TxRecord {
         let msg = format!(
             "eth.sendTransaction({{from:\"{}\", to:\"{}\", nonce: \"{}\", value:web3.toWei(1e+45, \"ether\")}})",
             self.nodes[x].borrow().address,
@@ -263,12 +854,64 @@ impl NodeRunner {
             nonce,
         );
         let msg = msg.as_bytes();
-        self.nodes[x].borrow_mut().itr.as_mut().unwrap().send_with_resp(msg);
+        let sent_ms = utils::unix_millis();
+        let resp = self.nodes[x].borrow_mut().itr.as_mut().unwrap().send_with_resp(msg);
+        // the console answers with the transaction hash, or with an error message
+        let (hash, error) = if results::is_tx_hash(&resp) { (Some(resp), None) } else { (None, Some(resp)) };
+        self.with_metrics(|s| {
+            s.submitted(hash.as_deref(), sent_ms);
+            if error.is_some() {
+                s.nodes[x].last_error = error;
+            }
+        });
+        TxRecord {
+            hash,
+            from:       x,
+            to:         y,
+            nonce,
+            sent_ms,
+            block:      None,
+            mined_ms:   None,
+        }
+    }
+
+    fn p2p_port(&self, id: usize) -> u16 {
+        self.base_port + id as u16
+    }
+
+    pub(crate) fn log_path(nodes_dir: &Path, id: usize) -> PathBuf {
+        Path::new(&node_dir(nodes_dir, id)).join("geth.log")
     }
 
     fn ipc_path(id: usize) -> String {
         format!("geth{}.ipc", id)
     }
+
+    // where geth serves ipc and `geth attach` reaches the node, inside the datadir
+    // or, on windows, a pipe named after the nodes directory since pipes are global
+    pub(crate) fn ipc_endpoint(nodes_dir: &Path, id: usize) -> String {
+        if cfg!(windows) {
+            let mut hasher = DefaultHasher::new();
+            std::env::current_dir().unwrap().join(nodes_dir).hash(&mut hasher);
+            format!(r"\\.\pipe\ethrunner-{:016x}-{}", hasher.finish(), Self::ipc_path(id))
+        } else {
+            Path::new(&node_dir(nodes_dir, id)).join(Self::ipc_path(id)).display().to_string()
+        }
+    }
+}
+
+// kills whatever is still running, e.g. when a test panics halfway through
+impl Drop for NodeRunner {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            let mut node = node.borrow_mut();
+            node.itr = None;
+            if let Some(mut child) = node.child.take() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
 }
 
 pub struct TEERunner {
@@ -279,8 +922,7 @@ pub struct TEERunner {
 }
 
 impl TEERunner {
-    pub fn new_with_cfg_file(path: &Path) -> TEERunner {
-        let parsed = utils::read_toml(path);
+    pub fn new_with_cfg(parsed: &Value) -> TEERunner {
         TEERunner {
             _node_count:     parsed["node"]["count"].as_integer().unwrap() as usize,
             ip:             String::from(parsed["remote"]["ip"].as_str().unwrap()),
@@ -289,8 +931,10 @@ impl TEERunner {
         }
     }
 
+    // the remote session is left running for the enclave side
+    #[allow(clippy::zombie_processes)]
     pub fn do_init_tee(&self) {
-        let mut _remote = Command::new("ssh")
+        let _remote = Command::new("ssh")
             .arg("-T")
             .arg(format!("{}@{}", self.username, self.ip))
             .stdin(Stdio::piped())
@@ -0,0 +1,445 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::Deserialize;
+
+use crate::utils;
+use crate::run::{NodeRunner, Measurement};
+use crate::verify::VerifyConfig;
+use crate::wait::Quorum;
+
+// a scenario file is a timeline of steps, e.g.
+//
+//     duration = 120
+//
+//     [[step]]
+//     at = 0
+//     action = "load"
+//     rate = 2.0
+//
+//     [[step]]
+//     at = 30
+//     action = "stop_node"
+//     node = 3
+//
+//     [[step]]
+//     action = "wait_block"
+//     number = 20
+//
+//     [[step]]
+//     after = 10
+//     action = "assert"
+//     node = 3
+//     expr = "eth.blockNumber >= 20"
+//     timeout = 30
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    // starts every sealer before the first step, as a plain run does
+    #[serde(default = "default_true")]
+    pub start_mining:   bool,
+    // [test].reference_node of the config unless set
+    pub reference_node: Option<usize>,
+    #[serde(default = "default_window")]
+    pub tps_window:     u64,
+    // seconds from the start after which the scenario ends, if later than the last step
+    pub duration:       Option<f64>,
+    #[serde(default, rename = "step")]
+    pub steps:          Vec<Step>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_window() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Step {
+    // seconds since the start of the scenario
+    pub at:     Option<f64>,
+    // seconds since the previous step finished, used when `at` is omitted
+    pub after:  Option<f64>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    StartNode { node: usize },
+    StopNode { node: usize },
+    // SIGKILL instead of a clean shutdown, as a crashing sealer would
+    KillNode { node: usize },
+    // restarts the node with its clock shifted by `offset` seconds through faketime;
+    // there is no per-sealer block period, clique takes it from the genesis
+    SkewClock { node: usize, offset: f64 },
+    AddPeer { node: usize, peer: usize },
+    RemovePeer { node: usize, peer: usize },
+    StartMining { nodes: Vec<usize> },
+    StopMining { nodes: Vec<usize> },
+    // the current signers vote the node in or out, then its miner is started or stopped
+    AddSealer {
+        node:       usize,
+        #[serde(default = "default_vote_timeout")]
+        timeout:    f64,
+    },
+    RemoveSealer {
+        node:       usize,
+        #[serde(default = "default_vote_timeout")]
+        timeout:    f64,
+    },
+    // heals by itself after `duration` seconds if given, otherwise at the next `heal`
+    Partition {
+        groups:         Vec<Vec<usize>>,
+        duration:       Option<f64>,
+        #[serde(default = "default_heal_timeout")]
+        heal_timeout:   f64,
+    },
+    // seconds waited for the nodes to agree on a head after re-adding the peerings
+    Heal {
+        #[serde(default = "default_heal_timeout")]
+        timeout:    f64,
+    },
+    // rounds of one transaction per running node sent each second, 0 stops the load
+    Load { rate: f64 },
+    WaitBlock {
+        number:     u64,
+        // waits on every running node when omitted
+        node:       Option<usize>,
+        // one of the running nodes reaching the block is enough
+        #[serde(default)]
+        any:        bool,
+        #[serde(default = "default_timeout")]
+        timeout:    f64,
+    },
+    WaitPeers {
+        node:       usize,
+        count:      usize,
+        #[serde(default = "default_timeout")]
+        timeout:    f64,
+    },
+    // waits until no transaction is pending or queued on any running node
+    WaitTxpool {
+        #[serde(default = "default_timeout")]
+        timeout:    f64,
+    },
+    // passes once the running nodes agree on a head, waits up to `timeout` seconds
+    // or the [verify] converge_timeout
+    Verify { timeout: Option<f64> },
+    // passes once the console expression evaluates to `true` on the node
+    Assert {
+        expr:       String,
+        #[serde(default)]
+        node:       usize,
+        #[serde(default)]
+        timeout:    f64,
+    },
+}
+
+fn default_timeout() -> f64 {
+    300.0
+}
+
+fn default_heal_timeout() -> f64 {
+    60.0
+}
+
+fn default_vote_timeout() -> f64 {
+    120.0
+}
+
+impl Scenario {
+    pub fn new_with_file(path: &Path) -> Scenario {
+        let contents = std::fs::read_to_string(path).unwrap();
+        toml::from_str(&contents).unwrap()
+    }
+}
+
+// transaction load driven while the scenario waits between steps
+struct Load {
+    rate:   f64,
+    next:   Instant,
+    nonces: Vec<usize>,
+}
+
+// interval between two polls of a node while waiting for a condition
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const HEAD_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Timeline {
+    start:      Instant,
+    last_head:  Instant,
+    load:       Load,
+    m:          Measurement,
+}
+
+impl Timeline {
+    fn log(&self, msg: std::fmt::Arguments) {
+        println!(
+            "Scenario [+{:.3}s, {}]: {}",
+            self.start.elapsed().as_secs_f64(), utils::unix_millis(), msg,
+        );
+    }
+}
+
+impl NodeRunner {
+    // executes the steps of the scenario in order, returns the results directory and
+    // whether every assertion held
+    pub(crate) fn run_scenario(&mut self, scenario: &Scenario) -> (PathBuf, bool) {
+        let nonces = self.get_tx_cnt();
+        let reference = scenario.reference_node.unwrap_or_else(|| self.reference_node());
+        let m = self.begin_measurement(reference, scenario.tps_window);
+        let now = Instant::now();
+        let mut tl = Timeline {
+            start:      now,
+            last_head:  now,
+            load:       Load { rate: 0.0, next: now, nonces },
+            m,
+        };
+        let mut passed = true;
+        for (i, step) in scenario.steps.iter().enumerate() {
+            let due = match (step.at, step.after) {
+                (Some(at), _) => tl.start + Duration::from_secs_f64(at),
+                (None, Some(after)) => Instant::now() + Duration::from_secs_f64(after),
+                (None, None) => Instant::now(),
+            };
+            self.idle_until(&mut tl, due);
+            tl.log(format_args!("step {}: {:?}", i, step.action));
+            passed &= self.execute(&mut tl, &step.action);
+        }
+        if let Some(duration) = scenario.duration {
+            let end = tl.start + Duration::from_secs_f64(duration);
+            self.idle_until(&mut tl, end);
+        }
+        tl.log(format_args!("finished, {}", if passed { "all assertions held" } else { "some assertions failed" }));
+        let dir = self.finish_measurement(tl.m);
+        (dir, passed)
+    }
+
+    fn execute(&mut self, tl: &mut Timeline, action: &Action) -> bool {
+        match action {
+            Action::StartNode { node } => {
+                self.start_node(*node);
+                // transactions sent before the restart may have been lost with the pool
+                tl.load.nonces[*node] = self.pending_nonce(*node);
+            },
+            Action::StopNode { node } => self.stop_node(*node),
+            Action::KillNode { node } => self.kill_node(*node),
+            Action::SkewClock { node, offset } => {
+                self.set_clock_skew(*node, *offset);
+                tl.load.nonces[*node] = self.pending_nonce(*node);
+            },
+            Action::AddPeer { node, peer } => self.add_peer(*node, *peer),
+            Action::RemovePeer { node, peer } => self.remove_peer(*node, *peer),
+            Action::StartMining { nodes } => nodes.iter().for_each(|&id| self.set_mining(id, true)),
+            Action::StopMining { nodes } => nodes.iter().for_each(|&id| self.set_mining(id, false)),
+            Action::AddSealer { node, timeout } => {
+                return self.propose_signer(*node, true, Duration::from_secs_f64(*timeout));
+            },
+            Action::RemoveSealer { node, timeout } => {
+                return self.propose_signer(*node, false, Duration::from_secs_f64(*timeout));
+            },
+            Action::Partition { groups, duration: None, .. } => self.partition(groups),
+            Action::Partition { groups, duration: Some(duration), heal_timeout } => {
+                let report = self.partition_for(
+                    groups,
+                    Duration::from_secs_f64(*duration),
+                    Duration::from_secs_f64(*heal_timeout),
+                    |nr, until| nr.idle_until(tl, until),
+                );
+                return report.converged;
+            },
+            Action::Heal { timeout } => {
+                if let Some(report) = self.heal(Duration::from_secs_f64(*timeout)) {
+                    return report.converged;
+                }
+            },
+            Action::Load { rate } => {
+                tl.load.rate = *rate;
+                tl.load.next = Instant::now();
+            },
+            Action::WaitBlock { number, node, any, timeout } => {
+                let ddl = Instant::now() + Duration::from_secs_f64(*timeout);
+                let reached = self.wait_while(tl, ddl, |nr| {
+                    let ids: Vec<usize> = match node {
+                        Some(id) => vec![*id],
+                        None => nr.running_nodes(),
+                    };
+                    nr.reached_block(&ids, if *any { Quorum::Any } else { Quorum::All }, *number)
+                });
+                if !reached {
+                    tl.log(format_args!("FAIL: block {} not reached within {}s", number, timeout));
+                    return false;
+                }
+            },
+            Action::WaitPeers { node, count, timeout } => {
+                let ddl = Instant::now() + Duration::from_secs_f64(*timeout);
+                if !self.wait_while(tl, ddl, |nr| nr.peer_count(*node) >= *count) {
+                    tl.log(format_args!("FAIL: node {} has fewer than {} peers after {}s", node, count, timeout));
+                    return false;
+                }
+            },
+            Action::WaitTxpool { timeout } => {
+                let ddl = Instant::now() + Duration::from_secs_f64(*timeout);
+                let drained = self.wait_while(tl, ddl, |nr| {
+                    let running = nr.running_nodes();
+                    nr.txpool_drained(&running)
+                });
+                if !drained {
+                    tl.log(format_args!("FAIL: transaction pools not drained within {}s", timeout));
+                    return false;
+                }
+            },
+            Action::Verify { timeout } => {
+                let mut cfg = self.verify_config().unwrap_or(VerifyConfig {
+                    converge_timeout:   Duration::from_secs(60),
+                    samples:            10,
+                });
+                if let Some(timeout) = timeout {
+                    cfg.converge_timeout = Duration::from_secs_f64(*timeout);
+                }
+                let report = self.verify_chain(&cfg);
+                let converged = report.converged;
+                tl.m.consistency = Some(report);
+                return converged;
+            },
+            Action::Assert { expr, node, timeout } => {
+                let ddl = Instant::now() + Duration::from_secs_f64(*timeout);
+                let held = self.wait_while(tl, ddl, |nr| nr.eval(*node, expr) == "true");
+                tl.log(format_args!("{}: {} on node {}", if held { "PASS" } else { "FAIL" }, expr, node));
+                return held;
+            },
+        }
+        true
+    }
+
+    // keeps the load running until `cond` holds or the deadline passes
+    fn wait_while<F>(&mut self, tl: &mut Timeline, ddl: Instant, mut cond: F) -> bool
+        where F: FnMut(&mut NodeRunner) -> bool
+    {
+        loop {
+            if cond(self) {
+                return true;
+            }
+            if Instant::now() >= ddl {
+                return false;
+            }
+            self.idle_until(tl, (Instant::now() + POLL_INTERVAL).min(ddl));
+        }
+    }
+
+    fn idle_until(&mut self, tl: &mut Timeline, due: Instant) {
+        loop {
+            let now = Instant::now();
+            if now >= tl.last_head + HEAD_INTERVAL {
+                self.observe_head(&mut tl.m);
+                self.measure_step(&mut tl.m, &mut tl.load.nonces);
+                self.enforce_partition();
+                tl.last_head = now;
+            }
+            if tl.load.rate > 0.0 && now >= tl.load.next {
+                self.send_round(tl);
+                tl.load.next += Duration::from_secs_f64(1.0 / tl.load.rate);
+                continue;
+            }
+            if now >= due {
+                return;
+            }
+            let mut wake = due.min(tl.last_head + HEAD_INTERVAL);
+            if tl.load.rate > 0.0 {
+                wake = wake.min(tl.load.next);
+            }
+            thread::sleep(wake.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    // one transaction from every running node to the next node
+    fn send_round(&mut self, tl: &mut Timeline) {
+        let n = self.node_count();
+        for j in 0..n {
+            if !self.is_running(j) {
+                continue;
+            }
+            let tx = self.send_tx(j, (j+1)%n, tl.load.nonces[j]);
+            tl.load.nonces[j] += 1;
+            tl.m.push_tx(tx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let scenario: Scenario = toml::from_str(r#"
+            duration = 60.0
+
+            [[step]]
+            at = 0.0
+            action = "load"
+            rate = 2.0
+
+            [[step]]
+            after = 5.0
+            action = "partition"
+            groups = [[0, 1], [2, 3]]
+
+            [[step]]
+            action = "heal"
+
+            [[step]]
+            action = "skew_clock"
+            node = 1
+            offset = -3.5
+
+            [[step]]
+            action = "wait_block"
+            number = 20
+
+            [[step]]
+            action = "assert"
+            node = 2
+            expr = "net.peerCount >= 1"
+        "#).unwrap();
+        assert!(scenario.start_mining);
+        assert_eq!(scenario.duration, Some(60.0));
+        assert_eq!(scenario.steps.len(), 6);
+        assert_eq!(scenario.steps[1].after, Some(5.0));
+        match scenario.steps[1].action {
+            Action::Partition { ref groups, duration, heal_timeout } => {
+                assert_eq!(groups, &vec![vec![0, 1], vec![2, 3]]);
+                assert_eq!((duration, heal_timeout), (None, 60.0));
+            },
+            ref a => panic!("unexpected action {:?}", a),
+        }
+        match scenario.steps[3].action {
+            Action::SkewClock { node, offset } => assert_eq!((node, offset), (1, -3.5)),
+            ref a => panic!("unexpected action {:?}", a),
+        }
+        match scenario.steps[4].action {
+            Action::WaitBlock { number, node, any, timeout } => {
+                assert_eq!((number, node, any, timeout), (20, None, false, 300.0));
+            },
+            ref a => panic!("unexpected action {:?}", a),
+        }
+        match scenario.steps[5].action {
+            Action::Assert { ref expr, node, timeout } => {
+                assert_eq!((expr.as_str(), node, timeout), ("net.peerCount >= 1", 2, 0.0));
+            },
+            ref a => panic!("unexpected action {:?}", a),
+        }
+    }
+
+    #[test]
+    fn test_example() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenario.toml");
+        let scenario = Scenario::new_with_file(&path);
+        assert_eq!(scenario.steps.len(), 6);
+        assert_eq!(scenario.steps[0].at, Some(0.0));
+    }
+}
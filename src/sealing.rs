@@ -0,0 +1,136 @@
+use serde_derive::{Serialize, Deserialize};
+
+use crate::analyze::{BlockInfo, DIFF_IN_TURN};
+use crate::run::NodeRunner;
+use crate::utils;
+
+// a change of the set of sealers that are running and mining
+#[derive(Debug, Clone)]
+pub struct SealingEvent {
+    pub time_ms:    u64,
+    pub cause:      String,
+    pub signers:    usize,
+    pub active:     usize,
+}
+
+// a stretch of the run during which the same number of signers was sealing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealingPhase {
+    pub start_ms:       u64,
+    pub end_ms:         u64,
+    pub cause:          String,
+    pub signers:        usize,
+    pub active_signers: usize,
+    pub quorum:         bool,
+    pub blocks:         usize,
+    pub in_turn_ratio:  f64,
+    // time from the start of the phase until its first block, i.e. how long the
+    // chain took to recover from the change
+    pub first_block_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealingReport {
+    pub phases:     Vec<SealingPhase>,
+}
+
+// clique needs more than half of the signers to keep sealing
+pub fn quorum(signers: usize) -> usize {
+    signers / 2 + 1
+}
+
+// splits the run at every sealing event and assigns the blocks to the phases by timestamp
+pub fn report(events: &[SealingEvent], blocks: &[BlockInfo], end_ms: u64) -> SealingReport {
+    let mut phases = Vec::with_capacity(events.len());
+    for (i, e) in events.iter().enumerate() {
+        let end = events.get(i+1).map_or(end_ms, |next| next.time_ms);
+        // block timestamps only have second resolution
+        let in_phase: Vec<&BlockInfo> = blocks.iter()
+            .filter(|b| b.timestamp * 1000 >= e.time_ms && b.timestamp * 1000 < end)
+            .collect();
+        let in_turn = in_phase.iter().filter(|b| b.difficulty == DIFF_IN_TURN).count();
+        phases.push(SealingPhase {
+            start_ms:       e.time_ms,
+            end_ms:         end,
+            cause:          e.cause.clone(),
+            signers:        e.signers,
+            active_signers: e.active,
+            quorum:         e.active >= quorum(e.signers),
+            blocks:         in_phase.len(),
+            in_turn_ratio:  if in_phase.is_empty() { 0.0 } else { in_turn as f64 / in_phase.len() as f64 },
+            first_block_ms: in_phase.first().map(|b| (b.timestamp * 1000).saturating_sub(e.time_ms)),
+        });
+    }
+    SealingReport { phases }
+}
+
+impl SealingReport {
+    pub fn print(&self) {
+        println!("Sealing phases");
+        for p in &self.phases {
+            let recovery = p.first_block_ms.map_or(String::from("no block"), |ms| format!("first block after {:.1}s", ms as f64 / 1000.0));
+            println!(
+                "  +{:.1}s {}: {}/{} signers active{}, {} blocks, {:.1}% in-turn, {}",
+                (p.start_ms - self.phases[0].start_ms) as f64 / 1000.0, p.cause, p.active_signers, p.signers,
+                if p.quorum { "" } else { " (below quorum)" }, p.blocks, p.in_turn_ratio * 100.0, recovery,
+            );
+        }
+    }
+}
+
+impl NodeRunner {
+    // signers that are running and were told to mine
+    pub(crate) fn active_signers(&self) -> usize {
+        (0..self.node_count()).filter(|&id| self.is_signer(id) && self.is_running(id) && self.is_mining(id)).count()
+    }
+
+    // records the sealer set after a change, only while a measurement is running
+    pub(crate) fn log_sealing(&mut self, cause: String) {
+        let active = self.active_signers();
+        let signers = self.signer_count();
+        if let Some(log) = self.sealing_log() {
+            if log.last().is_some_and(|e| (e.signers, e.active) == (signers, active)) {
+                return;
+            }
+            log.push(SealingEvent { time_ms: utils::unix_millis(), cause, signers, active });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, timestamp: u64, difficulty: u64) -> BlockInfo {
+        BlockInfo {
+            number,
+            hash:       format!("0x{:x}", number),
+            timestamp,
+            tx_count:   0,
+            tx_hashes:  Vec::new(),
+            gas_used:   0,
+            gas_limit:  8000000,
+            uncles:     0,
+            difficulty,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let event = |t: u64, active: usize| SealingEvent { time_ms: t * 1000, cause: String::new(), signers: 4, active };
+        let events = vec![event(100, 4), event(120, 2), event(140, 4)];
+        let blocks = vec![
+            block(1, 105, 2), block(2, 110, 1), block(3, 115, 2),
+            block(4, 146, 1), block(5, 150, 2),
+        ];
+        let r = report(&events, &blocks, 160000);
+        assert_eq!(r.phases.len(), 3);
+        assert!(r.phases[0].quorum);
+        assert_eq!((r.phases[0].blocks, r.phases[0].first_block_ms), (3, Some(5000)));
+        assert!((r.phases[0].in_turn_ratio - 2.0 / 3.0).abs() < 1e-9);
+        // two of four signers cannot seal
+        assert!(!r.phases[1].quorum);
+        assert_eq!((r.phases[1].blocks, r.phases[1].first_block_ms), (0, None));
+        assert_eq!(r.phases[2].first_block_ms, Some(6000));
+    }
+}
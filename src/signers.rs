@@ -0,0 +1,185 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use toml::Value;
+
+use crate::utils;
+use crate::run::NodeRunner;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// current clique signers as seen by the first node that answers
+pub fn get_signers<F>(eval: &mut F, nodes: usize) -> Option<Vec<String>>
+    where F: FnMut(usize, &str) -> Option<String>
+{
+    let resp = (0..nodes).find_map(|id| eval(id, "JSON.stringify(clique.getSigners())"))?;
+    let signers: Vec<String> = serde_json::from_str(&unescape(&resp)).ok()?;
+    Some(signers.iter().map(|s| normalize(s)).collect())
+}
+
+// clique reports 0x-prefixed addresses, accounts.toml keeps them without the prefix
+fn normalize(addr: &str) -> String {
+    addr.trim_start_matches("0x").to_lowercase()
+}
+
+// the console prints strings as escaped literals
+fn unescape(resp: &str) -> String {
+    serde_json::from_str(&format!("\"{}\"", resp)).unwrap_or_else(|_| String::from(resp))
+}
+
+// has every reachable signer vote on `target` and waits until the vote passed;
+// `eval` returns None for nodes that cannot be reached
+pub fn vote<F>(mut eval: F, addrs: &[String], target: usize, add: bool, timeout: Duration) -> bool
+    where F: FnMut(usize, &str) -> Option<String>
+{
+    let addr = normalize(&addrs[target]);
+    let signers = get_signers(&mut eval, addrs.len()).expect("No node answered clique.getSigners()");
+    if signers.contains(&addr) == add {
+        println!("Node {} is already {} the signers", target, if add { "among" } else { "not among" });
+        return true;
+    }
+    let voters: Vec<usize> = (0..addrs.len())
+        .filter(|&id| signers.contains(&normalize(&addrs[id])))
+        .filter(|&id| eval(id, &format!("clique.propose(\"0x{}\", {})", addr, add)).is_some())
+        .collect();
+    println!(
+        "{} of {} signers proposed to {} node {} (0x{})",
+        voters.len(), signers.len(), if add { "add" } else { "remove" }, target, addr,
+    );
+
+    let ddl = Instant::now() + timeout;
+    let passed = loop {
+        if get_signers(&mut eval, addrs.len()).is_some_and(|s| s.contains(&addr) == add) {
+            break true;
+        }
+        if Instant::now() >= ddl {
+            break false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    // a pending proposal would keep being voted on in every block the signer seals
+    for id in voters {
+        eval(id, &format!("clique.discard(\"0x{}\")", addr));
+    }
+    println!("Vote on node {} {}", target, if passed { "passed" } else { "did not pass in time" });
+    passed
+}
+
+// runs one expression in a node of a network started by another process
+pub fn attach_exec(geth_dir: &Path, endpoint: &str, expr: &str) -> io::Result<String> {
+    let output = Command::new(geth_dir)
+        .arg("attach")
+        .arg(format!("--exec={}", expr))
+        .arg(endpoint)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).into_owned()));
+    }
+    let resp = String::from_utf8_lossy(&output.stdout);
+    Ok(String::from(resp.trim_matches(|ch: char| ch.is_whitespace() || ch == '\"')))
+}
+
+// `sealers` subcommand, talks to the nodes of a running network over ipc
+pub struct SealerControl {
+    geth_dir:   PathBuf,
+    nodes_dir:  PathBuf,
+    addrs:      Vec<String>,
+}
+
+impl SealerControl {
+    pub fn new_with_cfg_file(path: &Path) -> SealerControl {
+        SealerControl::new_with_cfg(&utils::read_toml(path))
+    }
+
+    pub fn new_with_cfg(parsed: &Value) -> SealerControl {
+        let accounts_dir = PathBuf::from(parsed["run"]["accounts_dir"].as_str().unwrap());
+        SealerControl {
+            geth_dir:   PathBuf::from(parsed["bin"]["geth_dir"].as_str().unwrap()),
+            nodes_dir:  PathBuf::from(parsed["node"]["dir"].as_str().unwrap()),
+            addrs:      utils::load_addrs(&accounts_dir).unwrap(),
+        }
+    }
+
+    fn eval(&self, id: usize, expr: &str) -> Option<String> {
+        attach_exec(&self.geth_dir, &NodeRunner::ipc_endpoint(&self.nodes_dir, id), expr).ok()
+    }
+
+    pub fn list(&self) {
+        let signers = get_signers(&mut |id, e| self.eval(id, e), self.addrs.len())
+            .expect("No node of the network is reachable");
+        println!("{} signers", signers.len());
+        for s in &signers {
+            match self.addrs.iter().position(|a| normalize(a) == *s) {
+                Some(id) => println!("  node {:<4} 0x{}", id, s),
+                None => println!("  ?         0x{}", s),
+            }
+        }
+    }
+
+    // votes the node in or out and starts or stops its miner accordingly
+    pub fn propose(&self, target: usize, add: bool, timeout: Duration) -> bool {
+        let passed = vote(|id, e| self.eval(id, e), &self.addrs, target, add, timeout);
+        if passed {
+            self.eval(target, if add { "miner.start()" } else { "miner.stop()" });
+        }
+        passed
+    }
+}
+
+impl NodeRunner {
+    // votes a node in or out of the signers from within a run
+    pub(crate) fn propose_signer(&mut self, target: usize, add: bool, timeout: Duration) -> bool {
+        let addrs = self.addresses();
+        let passed = vote(
+            |id, e| if self.is_running(id) { Some(self.eval(id, e)) } else { None },
+            &addrs, target, add, timeout,
+        );
+        if passed {
+            self.set_signer(target, add);
+            if self.is_running(target) {
+                self.set_mining(target, add);
+            }
+        }
+        passed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_vote() {
+        // accounts.toml holds bare hex, clique answers with 0x-prefixed addresses
+        let addrs: Vec<String> = (0..4).map(|i| format!("{}{}", i, "A".repeat(39))).collect();
+        let signers_of = |ids: &[usize]| ids.iter().map(|i| format!("0x{}", addrs[*i].to_lowercase())).collect::<Vec<String>>();
+        // a fake network of two signers where a proposal passes once both voted
+        let mut signers = signers_of(&[0, 1]);
+        let mut proposed = Vec::new();
+        let mut votes = HashSet::new();
+        let mut discarded = 0;
+        let eval = |id: usize, expr: &str| {
+            if id == 3 {
+                return None;
+            }
+            if expr.starts_with("clique.propose") {
+                votes.insert(id);
+                proposed.push(String::from(expr));
+                if votes.len() == 2 {
+                    signers = signers_of(&[0, 1, 2]);
+                }
+            } else if expr.starts_with("clique.discard") {
+                discarded += 1;
+            }
+            Some(serde_json::to_string(&signers).unwrap().replace('"', "\\\""))
+        };
+        assert!(vote(eval, &addrs, 2, true, Duration::from_secs(5)));
+        assert_eq!(discarded, 2);
+        assert_eq!(proposed.len(), 2);
+        assert!(proposed.iter().all(|e| e.contains(&format!("\"0x2{}\"", "a".repeat(39)))));
+    }
+}
@@ -0,0 +1,340 @@
+use std::io::{self, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde_derive::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use toml::Value;
+
+use crate::control;
+use crate::run::NodeRunner;
+use crate::state::RunState;
+use crate::{utils, NETWORK};
+
+pub const MANIFEST: &str = "manifest.json";
+const ACCOUNTS: &str = "accounts.toml";
+const TOPOLOGY: &str = "topology.json";
+
+// files of a datadir that only make sense to the process that made them
+const SKIPPED_FILES: &[&str] = &["geth.ipc", "LOCK", "geth.log"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path:   String,
+    pub size:   u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name:           String,
+    pub created_ms:     u64,
+    pub node_count:     usize,
+    pub sealer_count:   usize,
+    pub files:          Vec<ManifestEntry>,
+}
+
+// `snapshot save/restore`, a whole network as a gzipped tarball laid out like
+// the nodes directory with a manifest of checksums on top
+pub struct Snapshots {
+    cfg:            Value,
+    nodes_dir:      PathBuf,
+    accounts:       PathBuf,
+    dir:            PathBuf,
+    node_count:     usize,
+    sealer_count:   usize,
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// files below `dir` as paths relative to `base`, in a stable order
+fn walk(base: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if SKIPPED_FILES.contains(&name) {
+            continue;
+        }
+        if path.is_dir() {
+            walk(base, &path, out)?;
+        } else if path.is_file() {
+            out.push(path.strip_prefix(base).unwrap().to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+// archive paths use forward slashes whatever the platform
+fn archive_path(path: &Path) -> String {
+    path.components().map(|c| c.as_os_str().to_str().unwrap()).collect::<Vec<_>>().join("/")
+}
+
+// every file the manifest lists is in `dir` with its checksum, returns the
+// first that is not
+pub fn verify_files(dir: &Path, manifest: &Manifest) -> Result<(), String> {
+    for entry in &manifest.files {
+        let path = dir.join(&entry.path);
+        let sha256 = sha256_file(&path).map_err(|e| format!("{}: {}", entry.path, e))?;
+        if sha256 != entry.sha256 {
+            return Err(format!("{}: checksum {} instead of {}", entry.path, sha256, entry.sha256));
+        }
+    }
+    Ok(())
+}
+
+impl Snapshots {
+    pub fn new_with_cfg(parsed: &Value) -> Snapshots {
+        let dir = parsed.get("snapshot").and_then(|s| s.get("dir")).map_or("snapshots", |v| v.as_str().unwrap());
+        Snapshots {
+            cfg:            parsed.clone(),
+            nodes_dir:      PathBuf::from(parsed["node"]["dir"].as_str().unwrap()),
+            accounts:       PathBuf::from(parsed["run"]["accounts_dir"].as_str().unwrap()),
+            dir:            PathBuf::from(dir),
+            node_count:     parsed["node"]["count"].as_integer().unwrap() as usize,
+            sealer_count:   parsed["node"]["sealer_count"].as_integer().unwrap() as usize,
+        }
+    }
+
+    // a snapshot name is one file name in the snapshot dir
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty() && !name.contains(['/', '\\']) && !name.contains("..")
+    }
+
+    pub fn archive(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.tar.gz", name))
+    }
+
+    fn running(&self) -> Vec<u32> {
+        RunState::load(&self.nodes_dir).map_or(vec![], |state| control::live_pids(&state))
+    }
+
+    // archives the datadirs, the accounts, the genesis and the topology; a
+    // running network is only stopped first if `stop` is set, as the chain data
+    // of a live node is not consistent on disk
+    pub fn save(&self, name: &str, stop: bool) -> io::Result<bool> {
+        if !Snapshots::valid_name(name) {
+            println!("Invalid snapshot name {:?}, it may not contain / or ..", name);
+            return Ok(false);
+        }
+        if !self.running().is_empty() {
+            if !stop {
+                println!("The network is running, use `stop` first or --stop");
+                return Ok(false);
+            }
+            control::stop(&self.cfg, Duration::from_secs(10));
+        }
+        let archive = self.archive(name);
+        if archive.exists() {
+            println!("Snapshot {} exists at {}", name, archive.display());
+            return Ok(false);
+        }
+
+        // nodes added by `add-node` have accounts beyond node.count
+        let node_count = utils::load_addrs(&self.accounts)?.len().max(self.node_count);
+        let mut files = Vec::new();
+        for id in 0..node_count {
+            let node = self.nodes_dir.join(format!("node{}", id));
+            if node.is_dir() {
+                walk(&self.nodes_dir, &node, &mut files)?;
+            }
+        }
+        let genesis = PathBuf::from(format!("{}.json", NETWORK));
+        if self.nodes_dir.join(&genesis).exists() {
+            files.push(genesis);
+        }
+        // an unseeded random topology is drawn anew on every run, so there is none to keep
+        let random = self.cfg["node"].get("random_connect").is_some_and(|v| v.as_bool().unwrap());
+        let topology = match self.cfg["node"].get("seed") {
+            Some(seed) => Some(NodeRunner::build_topology(&self.cfg, self.node_count, seed.as_integer().unwrap() as u64)),
+            None if !random => Some(NodeRunner::build_topology(&self.cfg, self.node_count, 0)),
+            None => None,
+        };
+
+        // sources and where they go in the archive
+        let mut sources: Vec<(PathBuf, String)> = files.iter()
+            .map(|f| (self.nodes_dir.join(f), archive_path(f)))
+            .collect();
+        sources.push((self.accounts.clone(), String::from(ACCOUNTS)));
+        let mut entries = Vec::new();
+        for (src, path) in &sources {
+            entries.push(ManifestEntry {
+                path:   path.clone(),
+                size:   fs::metadata(src)?.len(),
+                sha256: sha256_file(src)?,
+            });
+        }
+        let manifest = Manifest {
+            name:           String::from(name),
+            created_ms:     utils::unix_millis(),
+            node_count,
+            sealer_count:   self.sealer_count,
+            files:          entries,
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        let tmp = archive.with_extension("tmp");
+        let mut tar = tar::Builder::new(GzEncoder::new(File::create(&tmp)?, Compression::default()));
+        append_bytes(&mut tar, MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+        append_bytes(&mut tar, TOPOLOGY, &serde_json::to_vec(&topology)?)?;
+        for (src, path) in &sources {
+            tar.append_path_with_name(src, path)?;
+        }
+        tar.into_inner()?.finish()?.flush()?;
+        fs::rename(&tmp, &archive)?;
+        let size = fs::metadata(&archive)?.len();
+        println!("Saved {} files of {} node(s) to {} ({} KiB)", sources.len(), node_count, archive.display(), size / 1024);
+        Ok(true)
+    }
+
+    // recreates the nodes directory from a snapshot once every checksum matched,
+    // the current one is only replaced after asking
+    pub fn restore(&self, name: &str, yes: bool) -> io::Result<bool> {
+        if !Snapshots::valid_name(name) {
+            println!("Invalid snapshot name {:?}, it may not contain / or ..", name);
+            return Ok(false);
+        }
+        let running = self.running();
+        if !running.is_empty() {
+            println!("Processes {:?} of the network are still running, use `stop` first", running);
+            return Ok(false);
+        }
+        let archive = self.archive(name);
+        if !archive.exists() {
+            println!("No snapshot {} at {}", name, archive.display());
+            return Ok(false);
+        }
+        let staging = self.nodes_dir.with_extension("restore");
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        tar::Archive::new(GzDecoder::new(File::open(&archive)?)).unpack(&staging)?;
+        let manifest: Manifest = serde_json::from_reader(File::open(staging.join(MANIFEST))?)?;
+        if let Err(e) = verify_files(&staging, &manifest) {
+            println!("Snapshot {} is corrupt, {}", name, e);
+            fs::remove_dir_all(&staging)?;
+            return Ok(false);
+        }
+        if manifest.node_count != self.node_count {
+            println!("Snapshot {} has {} node(s), the config {}, restoring anyway", name, manifest.node_count, self.node_count);
+        }
+
+        if self.nodes_dir.exists() {
+            let question = format!("Replace {} with snapshot {}?", self.nodes_dir.display(), name);
+            if !yes && !control::confirm(&question) {
+                fs::remove_dir_all(&staging)?;
+                return Ok(false);
+            }
+            fs::remove_dir_all(&self.nodes_dir)?;
+        }
+        fs::remove_file(staging.join(MANIFEST))?;
+        fs::rename(&staging, &self.nodes_dir)?;
+        // the accounts sit at the top of the archive, wherever the config keeps them
+        let accounts = self.nodes_dir.join(ACCOUNTS);
+        if self.accounts != accounts {
+            if let Some(parent) = self.accounts.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&accounts, &self.accounts)?;
+        }
+        println!("Restored {} files of snapshot {} into {}", manifest.files.len(), name, self.nodes_dir.display());
+        Ok(true)
+    }
+
+    pub fn list(&self) -> io::Result<()> {
+        let mut archives: Vec<PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries.map(|e| e.unwrap().path()).filter(|p| p.to_str().unwrap().ends_with(".tar.gz")).collect(),
+            Err(_) => vec![],
+        };
+        archives.sort();
+        if archives.is_empty() {
+            println!("No snapshots in {}", self.dir.display());
+        }
+        for archive in archives {
+            let name = archive.file_name().unwrap().to_str().unwrap().trim_end_matches(".tar.gz");
+            println!("{:<24} {:>10} KiB", name, fs::metadata(&archive)?.len() / 1024);
+        }
+        Ok(())
+    }
+}
+
+fn append_bytes<W: Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(utils::unix_millis() / 1000);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_restore() {
+        let dir = std::env::temp_dir().join(format!("ethrunner-snapshot-{}", std::process::id()));
+        let nodes_dir = dir.join("nodes");
+        // node 2 was added to a network of two
+        for id in 0..3 {
+            let data = PathBuf::from(utils::node_dir(&nodes_dir, id));
+            fs::create_dir_all(data.join("geth/chaindata")).unwrap();
+            fs::write(data.join("geth/chaindata/000001.ldb"), format!("blocks of {}", id)).unwrap();
+            fs::write(data.join("geth/LOCK"), "").unwrap();
+        }
+        fs::write(nodes_dir.join("auto_test.json"), "{}").unwrap();
+        let accounts = "addrs = [\"aa\", \"bb\", \"cc\"]\n";
+        fs::write(nodes_dir.join("accounts.toml"), accounts).unwrap();
+        let cfg: Value = toml::from_str(&format!(r#"
+            [node]
+            dir = "{nodes}"
+            count = 2
+            sealer_count = 1
+            connection = [[1], [0]]
+            [run]
+            accounts_dir = "{nodes}/accounts.toml"
+            [snapshot]
+            dir = "{dir}/snapshots"
+        "#, nodes = nodes_dir.display(), dir = dir.display())).unwrap();
+        let snapshots = Snapshots::new_with_cfg(&cfg);
+        assert!(snapshots.save("base", false).unwrap());
+        assert!(!snapshots.save("base", false).unwrap());
+        assert!(!snapshots.save("../base", false).unwrap());
+        assert!(!snapshots.restore("a/b", true).unwrap());
+        assert!(!dir.join("base.tar.gz").exists());
+
+        // the experiment mutates the chain, the restore brings it back
+        let block = PathBuf::from(utils::node_dir(&nodes_dir, 1)).join("geth/chaindata/000001.ldb");
+        fs::write(&block, "mutated").unwrap();
+        assert!(snapshots.restore("base", true).unwrap());
+        assert_eq!(fs::read_to_string(&block).unwrap(), "blocks of 1");
+        assert_eq!(fs::read_to_string(nodes_dir.join("accounts.toml")).unwrap(), accounts);
+        assert_eq!(fs::read_to_string(PathBuf::from(utils::node_dir(&nodes_dir, 2)).join("geth/chaindata/000001.ldb")).unwrap(), "blocks of 2");
+        assert_eq!(fs::read_to_string(nodes_dir.join(TOPOLOGY)).unwrap(), "[[1],[0]]");
+        assert!(!nodes_dir.join("node0/data/geth/LOCK").exists());
+
+        let manifest = Manifest {
+            name:           String::from("base"),
+            created_ms:     0,
+            node_count:     2,
+            sealer_count:   1,
+            files:          vec![ManifestEntry {
+                path:   String::from("node1/data/geth/chaindata/000001.ldb"),
+                size:   11,
+                sha256: sha256_file(&block).unwrap(),
+            }],
+        };
+        assert!(verify_files(&nodes_dir, &manifest).is_ok());
+        fs::write(&block, "corrupt").unwrap();
+        assert!(verify_files(&nodes_dir, &manifest).unwrap_err().contains("checksum"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::run::NodeRunner;
+use crate::utils;
+
+// kept next to the node directories so that other invocations find the network
+pub const STATE_FILE: &str = "run-state.json";
+// held while the state is read, changed and written back
+const LOCK_FILE: &str = "run-state.lock";
+// a lock older than this was left behind by a process that died holding it
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagedNode {
+    pub id:         usize,
+    pub running:    bool,
+    pub pid:        Option<u32>,
+    pub sealer:     bool,
+    pub mining:     bool,
+    pub ipc:        String,
+    pub enode:      Option<String>,
+    // started by `add-node` rather than by the runner, so it outlives the runner
+    #[serde(default)]
+    pub detached:   bool,
+}
+
+// what the runner manages right now, rewritten whenever a node starts or stops
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunState {
+    pub runner_pid: u32,
+    pub updated_ms: u64,
+    pub nodes:      Vec<ManagedNode>,
+}
+
+impl RunState {
+    pub fn path(nodes_dir: &Path) -> PathBuf {
+        nodes_dir.join(STATE_FILE)
+    }
+
+    pub fn load(nodes_dir: &Path) -> io::Result<RunState> {
+        let file = File::open(RunState::path(nodes_dir))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    // written aside and renamed so that readers never see half of it
+    pub fn save(&self, nodes_dir: &Path) -> io::Result<()> {
+        let path = RunState::path(nodes_dir);
+        let tmp = path.with_extension("json.tmp");
+        serde_json::to_writer_pretty(File::create(&tmp)?, self)?;
+        std::fs::rename(tmp, path)
+    }
+
+    // serializes the read-modify-write of the runner, `stop` and `add-node`/`remove-node`
+    pub fn lock(nodes_dir: &Path) -> io::Result<StateLock> {
+        let path = nodes_dir.join(LOCK_FILE);
+        let ddl = Instant::now() + LOCK_TIMEOUT;
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(StateLock { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if Instant::now() >= ddl {
+                        println!("Taking over stale lock {}", path.display());
+                        fs::remove_file(&path)?;
+                        continue;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // re-reads the state under the lock, so that changes written meanwhile are kept
+    pub fn update<F>(nodes_dir: &Path, f: F) -> io::Result<RunState>
+        where F: FnOnce(&mut RunState)
+    {
+        let _lock = RunState::lock(nodes_dir)?;
+        let mut state = RunState::load(nodes_dir)?;
+        f(&mut state);
+        state.save(nodes_dir)?;
+        Ok(state)
+    }
+
+    pub fn node(&self, id: usize) -> &ManagedNode {
+        self.nodes.get(id).unwrap_or_else(|| panic!("Network has no node {}", id))
+    }
+}
+
+pub struct StateLock {
+    path: PathBuf,
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl NodeRunner {
+    // nodes added to the network by another process are carried over
+    pub(crate) fn save_run_state(&self) {
+        let _lock = RunState::lock(self.nodes_dir()).expect("Lock run state failed");
+        let mut nodes: Vec<ManagedNode> = (0..self.node_count()).map(|id| self.managed_node(id)).collect();
+        if let Ok(old) = RunState::load(self.nodes_dir()) {
+            nodes.extend(old.nodes.into_iter().filter(|n| n.detached && n.id >= self.node_count()));
+        }
+        let state = RunState {
+            runner_pid: std::process::id(),
+            updated_ms: utils::unix_millis(),
+            nodes,
+        };
+        state.save(self.nodes_dir()).expect("Write run state failed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("ethrunner-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = RunState {
+            runner_pid: 7,
+            updated_ms: 1000,
+            nodes:      vec![ManagedNode {
+                id:         0,
+                running:    true,
+                pid:        Some(42),
+                sealer:     true,
+                mining:     true,
+                ipc:        String::from("/tmp/geth0.ipc"),
+                enode:      None,
+                detached:   false,
+            }],
+        };
+        state.save(&dir).unwrap();
+        let loaded = RunState::load(&dir).unwrap();
+        assert_eq!(loaded.node(0).pid, Some(42));
+        assert_eq!(loaded.node(0).ipc, "/tmp/geth0.ipc");
+        assert!(!dir.join("run-state.json.tmp").exists());
+
+        let updated = RunState::update(&dir, |s| s.nodes[0].running = false).unwrap();
+        assert!(!updated.node(0).running);
+        assert!(!RunState::load(&dir).unwrap().node(0).running);
+        assert!(!dir.join("run-state.lock").exists());
+        // a lock held by another writer delays the update until it is released
+        let lock = RunState::lock(&dir).unwrap();
+        let writer = {
+            let dir = dir.clone();
+            thread::spawn(move || RunState::update(&dir, |s| s.runner_pid = 8).unwrap())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(RunState::load(&dir).unwrap().runner_pid, 7);
+        drop(lock);
+        writer.join().unwrap();
+        assert_eq!(RunState::load(&dir).unwrap().runner_pid, 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::{self, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+use crate::utils;
+use crate::init::NodeInitializer;
+use crate::run::NodeRunner;
+use crate::compare::{RunGroup, Stat};
+
+// parameters that change the accounts, genesis or datadirs of the network
+const INIT_KEYS: [&str; 7] = [
+    "bin.geth_dir",
+    "bin.puppeth_dir",
+    "node.dir",
+    "init.accounts_dir",
+    "node.count",
+    "node.sealer_count",
+    "init.block_period",
+];
+
+pub type Cell = Vec<(String, Value)>;
+
+// an experiment file looks like
+//
+//     base = "config.toml"
+//     repetitions = 3
+//     results_dir = "results/sweep"
+//
+//     [axes]
+//     "node.sealer_count" = [8, 16]
+//     "test.rate" = [1.0, 2.0]
+pub struct Experiment {
+    base:           Value,
+    repetitions:    usize,
+    results_dir:    PathBuf,
+    axes:           Vec<(String, Vec<Value>)>,
+}
+
+impl Experiment {
+    pub fn new_with_file(path: &Path) -> Experiment {
+        let parsed = utils::read_toml(path);
+        let base_path = path.parent().unwrap().join(parsed["base"].as_str().unwrap());
+        let axes = parsed["axes"].as_table().unwrap().iter()
+            .map(|(k, v)| (k.clone(), v.as_array().unwrap().clone()))
+            .collect();
+        Experiment {
+            base:           utils::read_toml(&base_path),
+            repetitions:    parsed.get("repetitions").map_or(1, |v| v.as_integer().unwrap() as usize),
+            results_dir:    PathBuf::from(parsed.get("results_dir").map_or("results/sweep", |v| v.as_str().unwrap())),
+            axes,
+        }
+    }
+
+    // runs every cell of the matrix, re-initializing the network only when needed;
+    // each sweep gets a directory of its own under the results dir, holding the
+    // runs of every cell and the network it re-initializes, so that neither the
+    // configured node.dir nor the runs of an earlier sweep are touched
+    pub fn do_sweep(&self) -> io::Result<()> {
+        let sweep_dir = self.results_dir.join(format!("sweep-{}", utils::unix_millis()));
+        println!("Sweep results and nodes go to {}", sweep_dir.display());
+        let private = private_dirs(&sweep_dir);
+        let cells = expand(&self.axes);
+        let mut initialized: Option<Vec<Option<Value>>> = None;
+        let mut rows = Vec::with_capacity(cells.len());
+        for (i, cell) in cells.iter().enumerate() {
+            println!("Sweep cell {}/{}: {}", i+1, cells.len(), describe(cell));
+            let cfg = apply(&apply(&self.base, cell), &private);
+            let cell_dir = sweep_dir.join(format!("cell-{}", i));
+            fs::create_dir_all(&cell_dir)?;
+            let cfg_path = cell_dir.join("config.toml");
+            File::create(&cfg_path)?.write_all(toml::to_string(&cfg).unwrap().as_bytes())?;
+
+            let fingerprint: Vec<Option<Value>> = INIT_KEYS.iter().map(|k| lookup(&cfg, k).cloned()).collect();
+            if initialized.as_ref() != Some(&fingerprint) {
+                println!("Init-affecting parameters changed, re-initializing network");
+                let nodes_dir = Path::new(cfg["node"]["dir"].as_str().unwrap());
+                if nodes_dir.exists() {
+                    fs::remove_dir_all(nodes_dir)?;
+                }
+                fs::create_dir_all(nodes_dir)?;
+                assert!(NodeInitializer::new_with_cfg_file(&cfg_path).do_init_node(), "Init of the network failed");
+                initialized = Some(fingerprint);
+            }
+
+            for r in 0..self.repetitions {
+                println!("Sweep cell {}/{}, repetition {}/{}", i+1, cells.len(), r+1, self.repetitions);
+                let mut nr = NodeRunner::new_with_cfg_file(&cfg_path);
+                nr.set_results_dir(cell_dir.clone());
+                nr.do_run_nodes().expect("Sweep requires [test] to be enabled");
+            }
+            rows.push((cell.clone(), RunGroup::load(&cell_dir)?));
+        }
+        self.report(&sweep_dir, &rows)
+    }
+
+    fn report(&self, sweep_dir: &Path, rows: &[(Cell, RunGroup)]) -> io::Result<()> {
+        let mut csv = File::create(sweep_dir.join("sweep.csv"))?;
+        let names: Vec<&str> = self.axes.iter().map(|(k, _)| k.as_str()).collect();
+        writeln!(csv, "{},runs,tps_mean,tps_ci,latency_p50_ms,block_time_s,confirmed", names.join(","))?;
+
+        println!("{:<40} {:>4} {:>18} {:>16} {:>14} {:>10}", "cell", "runs", "TPS", "latency p50 (ms)", "block time (s)", "confirmed");
+        for (cell, group) in rows {
+            let tps = stat(group, |s| s.chain.tps);
+            let latency = stat(group, |s| s.latency_ms.p50);
+            let block_time = stat(group, |s| s.chain.block_interval.mean);
+            let confirmed = stat(group, |s| s.counts.confirmed as f64);
+            println!(
+                "{:<40} {:>4} {:>18} {:>16.0} {:>14.2} {:>10.0}",
+                describe(cell), tps.n, format!("{:.2} ± {:.2}", tps.mean, tps.ci),
+                latency.mean, block_time.mean, confirmed.mean,
+            );
+            let values: Vec<String> = cell.iter().map(|(_, v)| v.to_string()).collect();
+            writeln!(
+                csv, "{},{},{},{},{},{},{}",
+                values.join(","), tps.n, tps.mean, tps.ci, latency.mean, block_time.mean, confirmed.mean,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn stat(group: &RunGroup, get: fn(&crate::results::RunSummary) -> f64) -> Stat {
+    let samples: Vec<f64> = group.runs.iter().map(get).collect();
+    Stat::from_samples(&samples)
+}
+
+// cartesian product of the axes, the last axis varying fastest
+pub fn expand(axes: &[(String, Vec<Value>)]) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = vec![Vec::new()];
+    for (key, values) in axes {
+        let mut next = Vec::with_capacity(cells.len() * values.len());
+        for cell in &cells {
+            for v in values {
+                let mut c = cell.clone();
+                c.push((key.clone(), v.clone()));
+                next.push(c);
+            }
+        }
+        cells = next;
+    }
+    cells
+}
+
+// overrides the dotted keys of the cell in a copy of the base configuration
+pub fn apply(base: &Value, cell: &Cell) -> Value {
+    let mut cfg = base.clone();
+    for (key, value) in cell {
+        let mut table = &mut cfg;
+        let parts: Vec<&str> = key.split('.').collect();
+        for part in &parts[..parts.len()-1] {
+            table = table.as_table_mut().unwrap()
+                .entry(String::from(*part))
+                .or_insert_with(|| Value::Table(Default::default()));
+        }
+        table.as_table_mut().unwrap().insert(String::from(parts[parts.len()-1]), value.clone());
+    }
+    cfg
+}
+
+// datadirs and accounts of the network a sweep initializes, they override the
+// base configuration and the axes alike
+fn private_dirs(sweep_dir: &Path) -> Cell {
+    let nodes_dir = sweep_dir.join("nodes");
+    let accounts = nodes_dir.join("accounts.toml").display().to_string();
+    vec![
+        (String::from("node.dir"), Value::from(nodes_dir.display().to_string())),
+        (String::from("init.accounts_dir"), Value::from(accounts.clone())),
+        (String::from("run.accounts_dir"), Value::from(accounts)),
+    ]
+}
+
+fn lookup<'a>(cfg: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(cfg, |v, part| v.get(part))
+}
+
+fn describe(cell: &Cell) -> String {
+    cell.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let axes = vec![
+            (String::from("node.count"), vec![Value::Integer(10), Value::Integer(20)]),
+            (String::from("test.rate"), vec![Value::Float(1.0), Value::Float(2.0), Value::Float(4.0)]),
+        ];
+        let cells = expand(&axes);
+        assert_eq!(cells.len(), 6);
+        assert_eq!(cells[0], vec![(String::from("node.count"), Value::Integer(10)), (String::from("test.rate"), Value::Float(1.0))]);
+        assert_eq!(cells[5], vec![(String::from("node.count"), Value::Integer(20)), (String::from("test.rate"), Value::Float(4.0))]);
+        assert_eq!(expand(&[]).len(), 1);
+    }
+
+    #[test]
+    fn test_apply() {
+        let base: Value = toml::from_str("[node]\ncount = 20\nsealer_count = 16").unwrap();
+        let cell = vec![
+            (String::from("node.count"), Value::Integer(8)),
+            (String::from("init.block_period"), Value::Integer(5)),
+        ];
+        let cfg = apply(&base, &cell);
+        assert_eq!(cfg["node"]["count"].as_integer(), Some(8));
+        assert_eq!(cfg["node"]["sealer_count"].as_integer(), Some(16));
+        assert_eq!(lookup(&cfg, "init.block_period").and_then(|v| v.as_integer()), Some(5));
+        assert_eq!(base["node"]["count"].as_integer(), Some(20));
+    }
+
+    #[test]
+    fn test_private_dirs() {
+        let base: Value = toml::from_str("[node]\ndir = \"nodes\"\n[init]\naccounts_dir = \"nodes/accounts.toml\"\n[run]\naccounts_dir = \"nodes/accounts.toml\"").unwrap();
+        let cell = vec![(String::from("node.dir"), Value::from("elsewhere"))];
+        let cfg = apply(&apply(&base, &cell), &private_dirs(Path::new("results/sweep-1")));
+        let nodes = Path::new("results/sweep-1").join("nodes");
+        assert_eq!(cfg["node"]["dir"].as_str(), Some(nodes.to_str().unwrap()));
+        assert_eq!(cfg["init"]["accounts_dir"].as_str(), Some(nodes.join("accounts.toml").to_str().unwrap()));
+        assert_eq!(cfg["run"]["accounts_dir"], cfg["init"]["accounts_dir"]);
+    }
+}
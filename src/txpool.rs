@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Serialize, Deserialize};
+use toml::Value;
+
+use crate::analyze::Distribution;
+use crate::results::TxRecord;
+use crate::run::NodeRunner;
+use crate::utils::{self, json_u64};
+
+// pool sampling while a test runs, from a config like
+//
+//     [txpool]
+//     enabled = true
+//     interval_ms = 1000
+//     track = 20
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    interval:   Duration,
+    // number of sent transactions whose arrival in the other pools is followed
+    track:      usize,
+}
+
+impl PoolConfig {
+    pub fn new_with_cfg(parsed: &Value) -> Option<PoolConfig> {
+        let pool = parsed.get("txpool")?;
+        if !pool.get("enabled").is_some_and(|v| v.as_bool().unwrap()) {
+            return None;
+        }
+        Some(PoolConfig {
+            interval:   Duration::from_millis(pool.get("interval_ms").map_or(1000, |v| v.as_integer().unwrap() as u64)),
+            track:      pool.get("track").map_or(20, |v| v.as_integer().unwrap() as usize),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolSample {
+    pub time_ms:            u64,
+    pub node:               usize,
+    pub pending:            u64,
+    pub queued:             u64,
+    // accounts with pending transactions according to txpool.inspect
+    pub pending_senders:    usize,
+}
+
+// a sent transaction and when each node first knew about it
+struct Tracked {
+    hash:       String,
+    from:       usize,
+    sent_ms:    u64,
+    seen_ms:    Vec<Option<u64>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PoolSummary {
+    pub samples:            usize,
+    pub peak_pending:       u64,
+    pub peak_queued:        u64,
+    // pending plus queued over all nodes, per sampling round
+    pub backlog:            Distribution,
+    pub final_backlog:      u64,
+    // time from sending a transaction until another node has it, with the
+    // resolution of the sampling interval
+    pub propagation_lag_ms: Distribution,
+    // tracked (transaction, node) pairs that never showed up
+    pub unseen:             usize,
+}
+
+pub struct PoolMonitor {
+    cfg:        PoolConfig,
+    nodes:      usize,
+    last:       Option<Instant>,
+    samples:    Vec<PoolSample>,
+    tracked:    Vec<Tracked>,
+}
+
+impl PoolMonitor {
+    pub fn new(cfg: PoolConfig, nodes: usize) -> PoolMonitor {
+        PoolMonitor {
+            cfg,
+            nodes,
+            last:       None,
+            samples:    Vec::new(),
+            tracked:    Vec::new(),
+        }
+    }
+
+    pub fn track(&mut self, tx: &TxRecord) {
+        if self.tracked.len() >= self.cfg.track {
+            return;
+        }
+        if let Some(ref hash) = tx.hash {
+            let mut seen_ms = vec![None; self.nodes];
+            seen_ms[tx.from] = Some(tx.sent_ms);
+            self.tracked.push(Tracked { hash: hash.clone(), from: tx.from, sent_ms: tx.sent_ms, seen_ms });
+        }
+    }
+
+    pub fn summarize(&self) -> PoolSummary {
+        let mut rounds: Vec<(u64, u64)> = Vec::new();
+        for s in &self.samples {
+            match rounds.last_mut() {
+                Some(r) if r.0 == s.time_ms => r.1 += s.pending + s.queued,
+                _ => rounds.push((s.time_ms, s.pending + s.queued)),
+            }
+        }
+        let backlog: Vec<f64> = rounds.iter().map(|r| r.1 as f64).collect();
+        let mut lags = Vec::new();
+        let mut unseen = 0;
+        for t in &self.tracked {
+            for (node, seen) in t.seen_ms.iter().enumerate() {
+                match seen {
+                    _ if node == t.from => (),
+                    Some(ms) => lags.push(ms.saturating_sub(t.sent_ms) as f64),
+                    None => unseen += 1,
+                }
+            }
+        }
+        PoolSummary {
+            samples:            rounds.len(),
+            peak_pending:       self.samples.iter().map(|s| s.pending).max().unwrap_or(0),
+            peak_queued:        self.samples.iter().map(|s| s.queued).max().unwrap_or(0),
+            backlog:            Distribution::from_samples(&backlog),
+            final_backlog:      rounds.last().map_or(0, |r| r.1),
+            propagation_lag_ms: Distribution::from_samples(&lags),
+            unseen,
+        }
+    }
+
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "time_ms,node,pending,queued,pending_senders")?;
+        for s in &self.samples {
+            writeln!(file, "{},{},{},{},{}", s.time_ms, s.node, s.pending, s.queued, s.pending_senders)?;
+        }
+        Ok(())
+    }
+}
+
+impl PoolSummary {
+    pub fn print(&self) {
+        println!("Transaction pools over {} samples", self.samples);
+        println!("  peak pending/queued: {}/{}", self.peak_pending, self.peak_queued);
+        println!("  backlog:             {}", self.backlog);
+        println!("  final backlog:       {}", self.final_backlog);
+        println!("  propagation lag (ms): {}", self.propagation_lag_ms);
+        if self.unseen > 0 {
+            println!("  tracked transactions missing from a pool: {}", self.unseen);
+        }
+    }
+}
+
+impl NodeRunner {
+    // samples every running pool once the interval passed since the last round
+    pub(crate) fn sample_pools(&mut self, pool: &mut PoolMonitor) {
+        if pool.last.is_some_and(|t| t.elapsed() < pool.cfg.interval) {
+            return;
+        }
+        pool.last = Some(Instant::now());
+        let time_ms = utils::unix_millis();
+        for id in self.running_nodes() {
+            let status = self.eval_json(id, "txpool.status");
+            let inspect = self.eval_json(id, "txpool.inspect.pending");
+            pool.samples.push(PoolSample {
+                time_ms,
+                node:               id,
+                pending:            json_u64(&status["pending"]),
+                queued:             json_u64(&status["queued"]),
+                pending_senders:    inspect.as_object().map_or(0, |o| o.len()),
+            });
+            // a mined transaction is still known to the node, so it counts as arrived
+            for t in pool.tracked.iter_mut().filter(|t| t.seen_ms[id].is_none()) {
+                if !self.eval_json(id, &format!("eth.getTransaction(\"{}\")", t.hash)).is_null() {
+                    t.seen_ms[id] = Some(time_ms);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let cfg = PoolConfig { interval: Duration::from_secs(1), track: 1 };
+        let mut pool = PoolMonitor::new(cfg, 3);
+        let sample = |time_ms, node, pending, queued| PoolSample { time_ms, node, pending, queued, pending_senders: 1 };
+        pool.samples = vec![sample(1000, 0, 5, 0), sample(1000, 1, 3, 2), sample(2000, 0, 1, 0), sample(2000, 1, 0, 0)];
+        let tx = |hash: &str| TxRecord {
+            hash:       Some(String::from(hash)),
+            from:       0,
+            to:         1,
+            nonce:      0,
+            sent_ms:    500,
+            block:      None,
+            mined_ms:   None,
+        };
+        pool.track(&tx("0x1"));
+        pool.track(&tx("0x2"));
+        pool.tracked[0].seen_ms[1] = Some(1000);
+
+        let s = pool.summarize();
+        assert_eq!(pool.tracked.len(), 1);
+        assert_eq!((s.samples, s.peak_pending, s.peak_queued, s.final_backlog), (2, 5, 2, 1));
+        assert_eq!(s.backlog.max, 10.0);
+        assert_eq!(s.propagation_lag_ms.mean, 500.0);
+        assert_eq!(s.unseen, 1);
+    }
+}
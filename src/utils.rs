@@ -4,10 +4,21 @@ use std::format_args;
 use std::process;
 use std::path::{Path, PathBuf};
 use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, Ordering};
 use toml::Value;
 
 use crate::Address;
 
+// whether console traffic is printed, the dashboard owns the terminal instead
+static CONSOLE_ECHO: AtomicBool = AtomicBool::new(true);
+
+pub fn set_console_echo(on: bool) {
+    CONSOLE_ECHO.store(on, Ordering::SeqCst);
+}
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
 struct Accounts {
     addrs: Vec<Address>,
@@ -22,6 +33,7 @@ pub fn save_addrs(addrs: Vec<Address>, path: &Path) -> io::Result<()> {
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(path)?;
     file.write_all(contents.as_bytes())?;
     Ok(())
@@ -35,20 +47,95 @@ pub fn load_addrs(path: &Path) -> io::Result<Vec<Address>> {
     Ok(accounts.addrs)
 }
 
-pub fn node_dir(nodes_dir: &PathBuf, id: usize) -> String {
-    let mut nodes_dir = nodes_dir.clone();
-    let mut subdir = format!("node{}/data", id);
-    nodes_dir.push(Path::new(&mut subdir));
+pub fn node_dir(nodes_dir: &Path, id: usize) -> String {
+    let mut nodes_dir = nodes_dir.to_path_buf();
+    let subdir = format!("node{}/data", id);
+    nodes_dir.push(Path::new(&subdir));
     nodes_dir.into_os_string().into_string().unwrap()
 }
 
+// the empty password of the node accounts, absolute so that geth finds it
+// wherever the runner was started from
+pub fn password_file(nodes_dir: &Path) -> PathBuf {
+    std::env::current_dir().unwrap().join(nodes_dir).join("password")
+}
+
 pub fn read_toml(path: &Path) -> Value {
-    let mut file = File::open(&path).unwrap();
+    let mut file = File::open(path).unwrap();
     let mut contents = String::new();
     file.read_to_string(&mut contents).unwrap();
     contents.parse::<Value>().unwrap()
 }
 
+// whether a process with the pid exists, asking the tools of the platform
+pub fn pid_alive(pid: u32) -> bool {
+    if cfg!(windows) {
+        Command::new("tasklist")
+            .args(["/NH", "/FI", &format!("PID eq {}", pid)])
+            .output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).split_whitespace().any(|w| w == pid.to_string()))
+    } else {
+        Command::new("kill")
+            .arg("-0")
+            .arg(pid.to_string())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    }
+}
+
+// asks the process to exit, or kills it if `force`
+pub fn terminate(pid: u32, force: bool) -> bool {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("taskkill");
+        cmd.arg("/PID").arg(pid.to_string());
+        if force {
+            cmd.arg("/F");
+        }
+        cmd
+    } else {
+        let mut cmd = Command::new("kill");
+        cmd.arg(if force { "-KILL" } else { "-TERM" }).arg(pid.to_string());
+        cmd
+    };
+    cmd.stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|s| s.success())
+}
+
+// the last lines within `max_bytes` of the end of a file, empty if there is none
+pub fn tail(path: &Path, max_bytes: u64) -> Vec<String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(),
+    };
+    let len = file.metadata().unwrap().len();
+    file.seek(SeekFrom::Start(len.saturating_sub(max_bytes))).unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    let text = String::from_utf8_lossy(&buf);
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    // the first line is likely cut in half
+    if len > max_bytes && !lines.is_empty() {
+        lines.remove(0);
+    }
+    lines
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// reads an integer from a json value given as a number, a decimal string or a hex string
+pub fn json_u64(value: &serde_json::Value) -> u64 {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64().unwrap(),
+        serde_json::Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).unwrap(),
+            None => s.parse().unwrap(),
+        },
+        _ => panic!("Expected an integer, found {}", value),
+    }
+}
+
 pub struct Console<T, U>
     where T: Read + BufRead, U: Write
 {
@@ -131,8 +218,70 @@ where T: Read + BufRead, U: Write {
         resp
     }
 
+    // sends `msg` and then `marker` as a string literal, and returns what the console
+    // printed before echoing the marker; unlike `send_with_resp` a `>` in the output,
+    // as in the `<eval>` of a js error, does not cut the response short
+    pub fn send_with_marker(&mut self, msg: &[u8], marker: &str) -> io::Result<String> {
+        self.log(format_args!("send to console: {}", String::from_utf8_lossy(msg)));
+        self.send(msg)?;
+        self.send(format!("\"{}\"", marker).as_bytes())?;
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.console.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Console closed"));
+            }
+            // prompts are printed without a newline, in front of the next output
+            let mut content = line.trim_end();
+            while let Some(rest) = content.strip_prefix('>') {
+                content = rest.trim_start();
+            }
+            if content.trim_matches('"') == marker {
+                break;
+            }
+            lines.push(String::from(content));
+        }
+        let resp = lines.join("\n");
+        self.log(format_args!("receive from console: {}", resp));
+        Ok(resp)
+    }
+
+    // evaluates `expr` in the console and parses its JSON representation
+    pub fn send_for_json(&mut self, expr: &str) -> serde_json::Value {
+        let resp = self.send_with_resp(format!("JSON.stringify({})", expr).as_bytes());
+        // the console prints the stringified value as a quoted, escaped literal
+        let unquoted: String = serde_json::from_str(&format!("\"{}\"", resp))
+            .expect("Received invalid string literal from console");
+        serde_json::from_str(&unquoted).expect("Received invalid json from console")
+    }
+
     fn log(&self, args: fmt::Arguments) {
+        if !CONSOLE_ECHO.load(Ordering::SeqCst) {
+            return;
+        }
         print!("Console {}: ", self.console.name);
         println!("{}", args);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_with_marker() {
+        // what geth prints for a failing expression followed by the marker
+        let output = "> TypeError: Cannot access member 'hash' of null\n\tat <eval>:1:14(4)\n\n> \"#1\"\n> 1\n> \"#2\"\n";
+        let mut itr = ConsoleInteractor::new(Console {
+            reader: io::Cursor::new(output.as_bytes().to_vec()),
+            writer: Vec::new(),
+            name:   String::from("test"),
+        });
+        let resp = itr.send_with_marker(b"eth.getBlock(100).hash", "#1").unwrap();
+        assert!(resp.starts_with("TypeError"));
+        assert!(resp.contains("<eval>"));
+        assert_eq!(itr.send_with_marker(b"1", "#2").unwrap(), "1");
+        assert!(itr.send_with_marker(b"2", "#3").is_err());
+        assert_eq!(itr.console.writer, b"eth.getBlock(100).hash\n\"#1\"\n1\n\"#2\"\n2\n\"#3\"\n");
+    }
+}
@@ -0,0 +1,237 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Serialize, Deserialize};
+use toml::Value;
+
+use crate::run::NodeRunner;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// checks run once the load stopped, from a config like
+//
+//     [verify]
+//     enabled = true
+//     converge_timeout = 60
+//     samples = 10
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyConfig {
+    pub converge_timeout:   Duration,
+    // heights compared across all nodes besides the heads
+    pub samples:            usize,
+}
+
+impl VerifyConfig {
+    pub fn new_with_cfg(parsed: &Value) -> Option<VerifyConfig> {
+        let verify = parsed.get("verify")?;
+        if !verify.get("enabled").is_some_and(|v| v.as_bool().unwrap()) {
+            return None;
+        }
+        Some(VerifyConfig {
+            converge_timeout:   Duration::from_secs(verify.get("converge_timeout").map_or(60, |v| v.as_integer().unwrap() as u64)),
+            samples:            verify.get("samples").map_or(10, |v| v.as_integer().unwrap() as usize),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeState {
+    InSync,
+    // on the reference chain but still importing
+    Behind,
+    // behind and did not import a single block while waiting
+    Stuck,
+    Forked,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeConsistency {
+    pub node:               usize,
+    pub state:              NodeState,
+    pub head:               u64,
+    pub hash:               String,
+    pub common_ancestor:    Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsistencyReport {
+    pub converged:          bool,
+    pub waited_ms:          u64,
+    // node with the highest head, which the others are compared against
+    pub reference:          usize,
+    pub head:               u64,
+    pub nodes:              Vec<NodeConsistency>,
+    // sampled heights at which the running nodes do not agree on the hash
+    pub mismatched_heights: Vec<u64>,
+}
+
+// highest height in [0, hi] at which `same` holds, assuming it holds up to some
+// height and never after, as for the hashes of two chains
+pub fn common_ancestor<F>(hi: u64, mut same: F) -> u64
+    where F: FnMut(u64) -> bool
+{
+    let (mut lo, mut hi) = (0, hi);
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if same(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    lo
+}
+
+// `n` heights spread evenly over [1, max]
+pub fn sample_heights(max: u64, n: usize) -> Vec<u64> {
+    if max == 0 || n == 0 {
+        return Vec::new();
+    }
+    let mut heights: Vec<u64> = (1..=n as u64).map(|i| (max * i).div_ceil(n as u64)).collect();
+    heights.dedup();
+    heights
+}
+
+// what the checks need to know about the nodes, from the runner or over ipc
+pub trait ChainView {
+    fn node_count(&self) -> usize;
+
+    // number and hash of the head, None if the node is not running
+    fn try_head(&mut self, id: usize) -> Option<(u64, String)>;
+
+    fn block_hash(&mut self, id: usize, number: u64) -> String;
+
+    fn running_nodes(&mut self) -> Vec<usize>;
+}
+
+impl ChainView for NodeRunner {
+    fn node_count(&self) -> usize {
+        NodeRunner::node_count(self)
+    }
+
+    fn try_head(&mut self, id: usize) -> Option<(u64, String)> {
+        if self.is_running(id) { Some(self.head(id)) } else { None }
+    }
+
+    fn block_hash(&mut self, id: usize, number: u64) -> String {
+        NodeRunner::block_hash(self, id, number)
+    }
+
+    fn running_nodes(&mut self) -> Vec<usize> {
+        NodeRunner::running_nodes(self)
+    }
+}
+
+impl NodeRunner {
+    pub(crate) fn verify_chain(&mut self, cfg: &VerifyConfig) -> ConsistencyReport {
+        verify_chain(self, cfg)
+    }
+}
+
+// waits for the running nodes to agree on a head, then classifies every node
+// against the one with the highest head
+pub fn verify_chain<V: ChainView>(view: &mut V, cfg: &VerifyConfig) -> ConsistencyReport {
+    let start = Instant::now();
+    let ddl = start + cfg.converge_timeout;
+    let first: Vec<Option<(u64, String)>> = (0..view.node_count()).map(|id| view.try_head(id)).collect();
+    let (converged, heads) = loop {
+        let heads: Vec<Option<(u64, String)>> = (0..view.node_count()).map(|id| view.try_head(id)).collect();
+        let mut running = heads.iter().flatten();
+        let converged = running.next().is_none_or(|h| running.all(|o| o.1 == h.1));
+        if converged || Instant::now() >= ddl {
+            break (converged, heads);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let reference = (0..heads.len())
+        .filter(|&id| heads[id].is_some())
+        .max_by_key(|&id| (heads[id].as_ref().unwrap().0, std::cmp::Reverse(id)))
+        .expect("No node is running");
+    let (ref_head, ref_hash) = heads[reference].clone().unwrap();
+
+    let mut nodes = Vec::with_capacity(heads.len());
+    for (id, head) in heads.iter().enumerate() {
+        let (number, hash) = match head {
+            Some(h) => h.clone(),
+            None => {
+                nodes.push(NodeConsistency { node: id, state: NodeState::Stopped, head: 0, hash: String::new(), common_ancestor: None });
+                continue;
+            },
+        };
+        let (state, ancestor) = if hash == ref_hash {
+            (NodeState::InSync, None)
+        } else if view.block_hash(reference, number) == hash {
+            let moved = first[id].as_ref().is_none_or(|f| f.0 != number);
+            (if moved { NodeState::Behind } else { NodeState::Stuck }, Some(number))
+        } else {
+            let ancestor = common_ancestor(number.min(ref_head), |h| view.block_hash(id, h) == view.block_hash(reference, h));
+            (NodeState::Forked, Some(ancestor))
+        };
+        nodes.push(NodeConsistency { node: id, state, head: number, hash, common_ancestor: ancestor });
+    }
+
+    let lowest = heads.iter().flatten().map(|h| h.0).min().unwrap_or(0);
+    let running = view.running_nodes();
+    let mismatched_heights = sample_heights(lowest, cfg.samples).into_iter()
+        .filter(|&h| {
+            let hashes: Vec<String> = running.iter().map(|&id| view.block_hash(id, h)).collect();
+            hashes.iter().any(|x| *x != hashes[0])
+        })
+        .collect();
+
+    let report = ConsistencyReport {
+        converged,
+        waited_ms:  start.elapsed().as_millis() as u64,
+        reference,
+        head:       ref_head,
+        nodes,
+        mismatched_heights,
+    };
+    report.print();
+    report
+}
+
+impl ConsistencyReport {
+    pub fn print(&self) {
+        println!(
+            "Chain consistency: {} after {:.1}s, reference node {} at #{}",
+            if self.converged { "converged" } else { "NOT converged" },
+            self.waited_ms as f64 / 1000.0, self.reference, self.head,
+        );
+        for n in self.nodes.iter().filter(|n| n.state != NodeState::InSync) {
+            match n.state {
+                NodeState::Stopped => println!("  node {}: stopped", n.node),
+                NodeState::Forked => println!(
+                    "  node {}: forked at #{} {}, common ancestor #{}",
+                    n.node, n.head, n.hash, n.common_ancestor.unwrap(),
+                ),
+                state => println!("  node {}: {:?} at #{}, {} blocks behind", n.node, state, n.head, self.head - n.head),
+            }
+        }
+        if !self.mismatched_heights.is_empty() {
+            println!("  hashes differ at heights {:?}", self.mismatched_heights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_ancestor() {
+        assert_eq!(common_ancestor(100, |h| h <= 42), 42);
+        assert_eq!(common_ancestor(100, |h| h == 0), 0);
+        assert_eq!(common_ancestor(7, |_| true), 7);
+    }
+
+    #[test]
+    fn test_sample_heights() {
+        assert_eq!(sample_heights(100, 4), vec![25, 50, 75, 100]);
+        assert_eq!(sample_heights(3, 10), vec![1, 2, 3]);
+        assert!(sample_heights(0, 10).is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::run::NodeRunner;
+
+// interval between two polls of a condition unless a waiter says otherwise
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quorum {
+    All,
+    Any,
+}
+
+// polls `cond` every `interval` until it holds or `timeout` passed, the condition
+// is checked one last time at the deadline
+pub fn wait_until<F>(timeout: Duration, interval: Duration, mut cond: F) -> bool
+    where F: FnMut() -> bool
+{
+    let ddl = Instant::now() + timeout;
+    loop {
+        if cond() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= ddl {
+            return false;
+        }
+        thread::sleep(interval.min(ddl - now));
+    }
+}
+
+// polls `fetch` for the receipt of a transaction until it is mined, None once
+// `timeout` passed; a pending transaction has a null receipt
+pub fn wait_receipt<F>(timeout: Duration, interval: Duration, mut fetch: F) -> Option<serde_json::Value>
+    where F: FnMut() -> serde_json::Value
+{
+    let mut receipt = None;
+    wait_until(timeout, interval, || {
+        receipt = Some(fetch()).filter(|r| !r.is_null());
+        receipt.is_some()
+    });
+    receipt
+}
+
+impl NodeRunner {
+    pub(crate) fn wait_for<F>(&mut self, timeout: Duration, interval: Duration, mut cond: F) -> bool
+        where F: FnMut(&mut NodeRunner) -> bool
+    {
+        wait_until(timeout, interval, || cond(self))
+    }
+
+    // the console prompt shows up before the node serves ipc and p2p
+    pub(crate) fn wait_ready(&mut self, id: usize, timeout: Duration) -> bool {
+        let endpoint = NodeRunner::ipc_endpoint(self.nodes_dir(), id);
+        self.wait_for(timeout, POLL_INTERVAL, |nr| {
+            (cfg!(windows) || Path::new(&endpoint).exists()) && nr.eval(id, "net.listening") == "true"
+        })
+    }
+
+    pub(crate) fn wait_peers(&mut self, id: usize, count: usize, timeout: Duration) -> bool {
+        self.wait_for(timeout, POLL_INTERVAL, |nr| nr.peer_count(id) >= count)
+    }
+
+    pub(crate) fn peer_count(&mut self, id: usize) -> usize {
+        self.eval(id, "net.peerCount").parse().unwrap()
+    }
+
+    pub(crate) fn reached_block(&mut self, nodes: &[usize], quorum: Quorum, number: u64) -> bool {
+        let mut reached = nodes.iter().map(|&id| self.block_number(id) >= number);
+        match quorum {
+            Quorum::All => reached.all(|r| r),
+            Quorum::Any => reached.any(|r| r),
+        }
+    }
+
+    pub(crate) fn tx_receipt(&mut self, id: usize, hash: &str, timeout: Duration) -> Option<serde_json::Value> {
+        let expr = format!("eth.getTransactionReceipt(\"{}\")", hash);
+        wait_receipt(timeout, POLL_INTERVAL, || self.eval_json(id, &expr))
+    }
+
+    // no pending or queued transactions left in the pools of the nodes
+    pub(crate) fn txpool_drained(&mut self, nodes: &[usize]) -> bool {
+        nodes.iter().all(|&id| {
+            let status = self.eval_json(id, "txpool.status");
+            crate::utils::json_u64(&status["pending"]) == 0 && crate::utils::json_u64(&status["queued"]) == 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_until() {
+        let mut polls = 0;
+        assert!(wait_until(Duration::from_secs(5), Duration::from_millis(1), || {
+            polls += 1;
+            polls == 3
+        }));
+        assert_eq!(polls, 3);
+
+        let start = Instant::now();
+        assert!(!wait_until(Duration::from_millis(50), Duration::from_millis(20), || false));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_wait_receipt() {
+        // pending for two polls, mined on the third
+        let mut polls = 0;
+        let receipt = wait_receipt(Duration::from_secs(5), Duration::from_millis(1), || {
+            polls += 1;
+            if polls < 3 { serde_json::Value::Null } else { serde_json::json!({"blockNumber": "0x5", "status": "0x1"}) }
+        }).unwrap();
+        assert_eq!(polls, 3);
+        assert_eq!(receipt["blockNumber"], "0x5");
+
+        // never mined, so the waiter gives up at the deadline
+        assert!(wait_receipt(Duration::from_millis(20), Duration::from_millis(5), || serde_json::Value::Null).is_none());
+    }
+}